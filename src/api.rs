@@ -1,34 +1,67 @@
 use actix_web::{
-    get, post,
+    delete, get, post,
     web::{self, Data, Json},
     App, HttpResponse, HttpServer, Responder,
 };
-use jiff::{civil::Time, tz::TimeZone};
-use tokio::sync::watch::{Receiver, Sender};
+use jiff::{
+    civil::{Date, Time},
+    tz::TimeZone,
+    SignedDuration, Timestamp,
+};
+use serde::Deserialize;
+use std::{collections::BTreeMap, time::Duration};
+use tokio::{
+    fs,
+    sync::watch::{Receiver, Sender},
+};
 
 use crate::{
-    frank::FrankStateLock,
-    settings::{HeatAlarm, Settings, SettingsError, VibrationAlarm},
+    calendar,
+    frank::{
+        command::{FrankCommand, SideTarget},
+        error::FrankError,
+        handle::{CommandResult, FrankHandle},
+        history::HistoryLock,
+        FrankStateLock,
+    },
+    profile::ProfilePoint,
+    scheduler,
+    settings::{
+        DaySettings, Gain, HeatAlarm, Nap, Ramp, ScheduleException, Settings, SettingsError,
+        TempOverride, VibrationAlarm, VibrationPattern, Weekday,
+    },
+    temp::{Temp, TempUnit},
     SETTINGS_FILE,
 };
 
 const NUM_WORKERS: usize = 1;
+/// How long to wait for Frank to answer a state refresh
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long to wait for Frank to run a command
+const CMD_TIMEOUT: Duration = Duration::from_secs(60);
+/// The gentle alarm at the end of a nap, when one is asked for
+const NAP_ALARM: VibrationAlarm = VibrationAlarm {
+    pattern: VibrationPattern::Rise,
+    intensity: 30,
+    duration: 60,
+    offset: 0,
+};
 
 pub async fn run(
+    frank: FrankHandle,
     frank_state: FrankStateLock,
+    history: HistoryLock,
     settings_tx: Sender<Settings>,
     settings_rx: Receiver<Settings>,
 ) -> std::io::Result<()> {
     let server = HttpServer::new(move || {
         App::new()
+            .app_data(Data::new(frank.clone()))
             .app_data(Data::new(frank_state.clone()))
+            .app_data(Data::new(history.clone()))
             .app_data(Data::new(settings_rx.clone()))
             .app_data(Data::new(settings_tx.clone()))
-            .service(get_health)
-            .service(get_state)
-            .service(get_settings)
-            .service(post_settings)
-            .configure(cfg_settings_routes)
+            .configure(configure)
     })
     .workers(NUM_WORKERS)
     .bind(("0.0.0.0", 3000))?;
@@ -38,6 +71,48 @@ pub async fn run(
     Ok(())
 }
 
+/// Registers every endpoint. Expects the shared state
+/// to already be registered as app data
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_health)
+        .service(get_state)
+        .service(post_state_refresh)
+        .service(get_history)
+        .service(get_settings)
+        .service(post_settings)
+        .service(get_timezone)
+        .service(post_timezone)
+        .service(get_away_mode)
+        .service(post_away_mode)
+        .service(get_prime)
+        .service(post_prime)
+        .service(get_led_brightness)
+        .service(post_led_brightness)
+        .service(get_gain)
+        .service(post_gain)
+        .service(get_side_gain)
+        .service(post_side_gain)
+        .service(get_state_interval)
+        .service(post_state_interval)
+        .service(post_alarm_dismiss)
+        .service(post_alarm_snooze)
+        .service(get_override)
+        .service(post_override)
+        .service(get_nap)
+        .service(post_nap)
+        .service(get_exceptions)
+        .service(post_exception)
+        .service(delete_exception)
+        .service(get_calendar)
+        .service(post_calendar_preview)
+        .service(post_calendar)
+        .service(post_action_prime)
+        .service(post_action_temp)
+        .service(post_action_alarm)
+        .service(post_action_clear_alarm)
+        .configure(cfg_settings_routes);
+}
+
 #[get("/health")]
 async fn get_health(frank_state: Data<FrankStateLock>) -> impl Responder {
     match frank_state.read().await.valid {
@@ -46,9 +121,59 @@ async fn get_health(frank_state: Data<FrankStateLock>) -> impl Responder {
     }
 }
 
+#[derive(Deserialize)]
+struct StateQuery {
+    unit: Option<TempUnit>,
+}
+
 #[get("/state")]
-async fn get_state(frank_state: Data<FrankStateLock>) -> impl Responder {
-    Json(frank_state.read().await.clone())
+async fn get_state(
+    frank_state: Data<FrankStateLock>,
+    query: web::Query<StateQuery>,
+) -> Result<impl Responder, SettingsError> {
+    let state = frank_state.read().await;
+    let mut json = serde_json::to_value(&*state)?;
+    // temperatures are heat levels unless asked otherwise
+    if let Some(unit) = query.unit {
+        for (key, temp) in [("cur_temp", &state.cur_temp), ("tar_temp", &state.tar_temp)] {
+            json[key] = serde_json::json!({
+                "left": Temp::from_level(temp.left, unit),
+                "right": Temp::from_level(temp.right, unit),
+            });
+        }
+    }
+    Ok(Json(json))
+}
+
+#[post("/state/refresh")]
+async fn post_state_refresh(frank: Data<FrankHandle>) -> impl Responder {
+    match frank.refresh(REFRESH_TIMEOUT).await {
+        Ok(state) => HttpResponse::Ok().json(state),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    from: Option<Timestamp>,
+    to: Option<Timestamp>,
+    side: Option<SideTarget>,
+}
+
+#[get("/history")]
+async fn get_history(
+    history: Data<HistoryLock>,
+    query: web::Query<HistoryQuery>,
+) -> impl Responder {
+    let history = history.read().await;
+    let samples = history.query(query.from, query.to);
+    match &query.side {
+        Some(side @ (SideTarget::Left | SideTarget::Right)) => {
+            let samples: Vec<_> = samples.filter_map(|s| s.side(side)).collect();
+            HttpResponse::Ok().json(samples)
+        }
+        _ => HttpResponse::Ok().json(samples.collect::<Vec<_>>()),
+    }
 }
 
 #[get("/settings")]
@@ -61,7 +186,11 @@ async fn post_settings(
     settings_tx: Data<Sender<Settings>>,
     new_settings: Json<Settings>,
 ) -> impl Responder {
-    let new_settings = new_settings.into_inner();
+    let mut new_settings = new_settings.into_inner();
+    new_settings.number_exceptions();
+    if let Err(e) = new_settings.validate() {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
     if let Err(e) = new_settings.save(SETTINGS_FILE) {
         return HttpResponse::InternalServerError().body(e.to_string());
     }
@@ -175,6 +304,512 @@ async fn post_led_brightness(
     HttpResponse::Ok().body("OK")
 }
 
+#[get("/gain")]
+async fn get_gain(settings_rx: Data<Receiver<Settings>>) -> impl Responder {
+    let settings = settings_rx.borrow();
+    Json(settings.gain)
+}
+
+#[post("/gain")]
+async fn post_gain(
+    settings_rx: Data<Receiver<Settings>>,
+    settings_tx: Data<Sender<Settings>>,
+    value: Json<Gain>,
+) -> impl Responder {
+    let mut settings = settings_rx.borrow().clone();
+    settings.gain = value.into_inner();
+    if let Err(e) = settings.validate() {
+        return HttpResponse::BadRequest().body(e.to_string());
+    }
+
+    if let Err(e) = settings.save(SETTINGS_FILE) {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    if settings_tx.send(settings).is_err() {
+        return HttpResponse::InternalServerError().body("settings watch channel closed");
+    }
+    HttpResponse::Ok().body("OK")
+}
+
+#[get("/{side}/gain")]
+async fn get_side_gain(
+    settings_rx: Data<Receiver<Settings>>,
+    side: web::Path<SideTarget>,
+) -> impl Responder {
+    let settings = settings_rx.borrow();
+    Json(settings.gain.get(&side))
+}
+
+#[post("/{side}/gain")]
+async fn post_side_gain(
+    settings_rx: Data<Receiver<Settings>>,
+    settings_tx: Data<Sender<Settings>>,
+    side: web::Path<SideTarget>,
+    value: Json<u16>,
+) -> impl Responder {
+    let mut settings = settings_rx.borrow().clone();
+    settings.gain.set(&side, value.into_inner());
+    if let Err(e) = settings.validate() {
+        return HttpResponse::BadRequest().body(e.to_string());
+    }
+
+    if let Err(e) = settings.save(SETTINGS_FILE) {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    if settings_tx.send(settings).is_err() {
+        return HttpResponse::InternalServerError().body("settings watch channel closed");
+    }
+    HttpResponse::Ok().body("OK")
+}
+
+#[get("/state_interval")]
+async fn get_state_interval(settings_rx: Data<Receiver<Settings>>) -> impl Responder {
+    let settings = settings_rx.borrow();
+    Json(settings.state_interval)
+}
+
+#[post("/state_interval")]
+async fn post_state_interval(
+    settings_rx: Data<Receiver<Settings>>,
+    settings_tx: Data<Sender<Settings>>,
+    value: Json<u32>,
+) -> impl Responder {
+    let mut settings = settings_rx.borrow().clone();
+    settings.state_interval = value.into_inner();
+
+    if let Err(e) = settings.save(SETTINGS_FILE) {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    if settings_tx.send(settings).is_err() {
+        return HttpResponse::InternalServerError().body("settings watch channel closed");
+    }
+    HttpResponse::Ok().body("OK")
+}
+
+/// Stops `side`'s alarm. Frank clears both sides,
+/// so the other side's alarm is armed again
+#[post("/{side}/alarm/dismiss")]
+async fn post_alarm_dismiss(
+    frank: Data<FrankHandle>,
+    settings_rx: Data<Receiver<Settings>>,
+    side: web::Path<SideTarget>,
+) -> Result<impl Responder, SettingsError> {
+    let side = side.into_inner();
+    settings_rx.borrow().side(&side)?;
+
+    Ok(match frank.exec(FrankCommand::ClearAlarm(side), CMD_TIMEOUT).await {
+        Ok(()) => HttpResponse::Ok().body("OK"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    })
+}
+
+/// Stops `side`'s alarm and sets it to go off again in `minutes`
+#[post("/{side}/alarm/snooze")]
+async fn post_alarm_snooze(
+    frank: Data<FrankHandle>,
+    settings_rx: Data<Receiver<Settings>>,
+    side: web::Path<SideTarget>,
+    minutes: Json<u16>,
+) -> Result<impl Responder, SettingsError> {
+    let side = side.into_inner();
+    let (alarm, tz) = {
+        let settings = settings_rx.borrow();
+        let alarm = settings.side(&side)?.vibration.clone();
+        (alarm.ok_or(SettingsError::NoVibration)?, settings.timezone.clone())
+    };
+    let snooze = SignedDuration::from_mins(minutes.into_inner().into());
+    let at = (Timestamp::now() + snooze).to_zoned(tz);
+
+    let res = match frank
+        .exec(FrankCommand::ClearAlarm(side.clone()), CMD_TIMEOUT)
+        .await
+    {
+        Ok(()) => {
+            let cmd = FrankCommand::SetAlarm(side, Box::new((alarm, at)));
+            frank.exec(cmd, CMD_TIMEOUT).await
+        }
+        Err(e) => Err(e),
+    };
+    Ok(match res {
+        Ok(()) => HttpResponse::Ok().body("OK"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    })
+}
+
+#[get("/{side}/override")]
+async fn get_override(
+    settings_rx: Data<Receiver<Settings>>,
+    side: web::Path<SideTarget>,
+) -> Result<impl Responder, SettingsError> {
+    let settings = settings_rx.borrow();
+    let side = settings.side(&side)?;
+    Ok(Json(side.active_override(Timestamp::now()).cloned()))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Wake {
+    Wake,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OverrideUntil {
+    Wake(Wake),
+    At(Timestamp),
+}
+
+#[derive(Deserialize)]
+struct OverrideRequest {
+    temp: Temp,
+    minutes: Option<u32>,
+    until: Option<OverrideUntil>,
+}
+
+/// Sets a side to a temperature by hand, pausing its profile
+/// until the override ends. `null` ends it early.
+#[post("/{side}/override")]
+async fn post_override(
+    settings_rx: Data<Receiver<Settings>>,
+    settings_tx: Data<Sender<Settings>>,
+    side: web::Path<SideTarget>,
+    req: Json<Option<OverrideRequest>>,
+) -> Result<impl Responder, SettingsError> {
+    let side = side.into_inner();
+    let mut settings = settings_rx.borrow().clone();
+    let now = Timestamp::now();
+
+    let temp_override = match req.into_inner() {
+        Some(req) => {
+            let until = match (req.minutes, req.until) {
+                (Some(minutes), None) => now + SignedDuration::from_mins(minutes.into()),
+                (None, Some(OverrideUntil::At(at))) => at,
+                (None, Some(OverrideUntil::Wake(_))) => {
+                    let now = now.to_zoned(settings.timezone.clone());
+                    scheduler::next_wake(settings.side(&side)?, &now)
+                        .map_err(|_| SettingsError::InvalidOverride("no next wake"))?
+                        .timestamp()
+                }
+                _ => {
+                    return Err(SettingsError::InvalidOverride(
+                        "expected either `minutes` or `until`",
+                    ))
+                }
+            };
+            if until <= now {
+                return Err(SettingsError::InvalidOverride("it would already be over"));
+            }
+            Some(TempOverride {
+                temp: req.temp,
+                until,
+            })
+        }
+        None => None,
+    };
+    settings.side_mut(&side)?.temp_override = temp_override;
+    settings.validate()?;
+
+    if let Err(e) = settings.save(SETTINGS_FILE) {
+        return Ok(HttpResponse::InternalServerError().body(e.to_string()));
+    }
+    if settings_tx.send(settings).is_err() {
+        return Ok(HttpResponse::InternalServerError().body("settings watch channel closed"));
+    }
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+#[get("/{side}/nap")]
+async fn get_nap(
+    settings_rx: Data<Receiver<Settings>>,
+    side: web::Path<SideTarget>,
+) -> Result<impl Responder, SettingsError> {
+    let settings = settings_rx.borrow();
+    let side = settings.side(&side)?;
+    Ok(Json(side.active_nap(Timestamp::now()).cloned()))
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NapStart {
+    At(Timestamp),
+    /// the next time it is this time of day
+    Time(Time),
+}
+
+#[derive(Deserialize)]
+struct NapRequest {
+    temp: Temp,
+    minutes: u16,
+    /// now if not set
+    start: Option<NapStart>,
+    #[serde(default)]
+    vibrate: bool,
+}
+
+/// Starts a nap now or later, pausing the side's profile while it
+/// lasts without changing the rest of its night. `null` cancels it.
+#[post("/{side}/nap")]
+async fn post_nap(
+    settings_rx: Data<Receiver<Settings>>,
+    settings_tx: Data<Sender<Settings>>,
+    side: web::Path<SideTarget>,
+    req: Json<Option<NapRequest>>,
+) -> Result<impl Responder, SettingsError> {
+    let side = side.into_inner();
+    let mut settings = settings_rx.borrow().clone();
+    let now = Timestamp::now();
+
+    let nap = match req.into_inner() {
+        Some(req) => {
+            let start = match req.start {
+                None => now,
+                Some(NapStart::At(at)) => at,
+                Some(NapStart::Time(time)) => {
+                    let now = now.to_zoned(settings.timezone.clone());
+                    let next = now
+                        .with()
+                        .time(time)
+                        .build()
+                        .and_then(|at| if at <= now { at.tomorrow() } else { Ok(at) })
+                        .map_err(|_| SettingsError::InvalidNap("no such time"))?;
+                    next.timestamp()
+                }
+            };
+            let nap = Nap {
+                start,
+                minutes: req.minutes,
+                temp: req.temp,
+                vibration: req.vibrate.then_some(NAP_ALARM),
+            };
+            if nap.end() <= now {
+                return Err(SettingsError::InvalidNap("it would already be over"));
+            }
+            Some(nap)
+        }
+        None => None,
+    };
+    settings.drop_ended_naps(now);
+    settings.side_mut(&side)?.nap = nap;
+    settings.validate()?;
+
+    if let Err(e) = settings.save(SETTINGS_FILE) {
+        return Ok(HttpResponse::InternalServerError().body(e.to_string()));
+    }
+    if settings_tx.send(settings).is_err() {
+        return Ok(HttpResponse::InternalServerError().body("settings watch channel closed"));
+    }
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+/// The date in the settings' timezone
+fn today(settings: &Settings) -> Date {
+    Timestamp::now().to_zoned(settings.timezone.clone()).date()
+}
+
+#[get("/{side}/exceptions")]
+async fn get_exceptions(
+    settings_rx: Data<Receiver<Settings>>,
+    side: web::Path<SideTarget>,
+) -> Result<impl Responder, SettingsError> {
+    let settings = settings_rx.borrow();
+    let today = today(&settings);
+    let exceptions: Vec<_> = settings
+        .side(&side)?
+        .exceptions
+        .iter()
+        .filter(|e| !e.expired(today))
+        .cloned()
+        .collect();
+    Ok(Json(exceptions))
+}
+
+/// Adds an exception to a side's schedule, responding with its new `id`
+#[post("/{side}/exceptions")]
+async fn post_exception(
+    settings_rx: Data<Receiver<Settings>>,
+    settings_tx: Data<Sender<Settings>>,
+    side: web::Path<SideTarget>,
+    exception: Json<ScheduleException>,
+) -> Result<impl Responder, SettingsError> {
+    let mut settings = settings_rx.borrow().clone();
+    let today = today(&settings);
+    let mut exception = exception.into_inner();
+    if exception.expired(today) {
+        return Err(SettingsError::InvalidException("it would already be over"));
+    }
+
+    settings.drop_expired(today);
+    let exceptions = &mut settings.side_mut(&side)?.exceptions;
+    exception.id = exceptions.iter().map(|e| e.id).max().unwrap_or(0) + 1;
+    exceptions.push(exception.clone());
+    settings.validate()?;
+
+    if let Err(e) = settings.save(SETTINGS_FILE) {
+        return Ok(HttpResponse::InternalServerError().body(e.to_string()));
+    }
+    if settings_tx.send(settings).is_err() {
+        return Ok(HttpResponse::InternalServerError().body("settings watch channel closed"));
+    }
+    Ok(HttpResponse::Ok().json(exception))
+}
+
+#[delete("/{side}/exceptions/{id}")]
+async fn delete_exception(
+    settings_rx: Data<Receiver<Settings>>,
+    settings_tx: Data<Sender<Settings>>,
+    path: web::Path<(SideTarget, u32)>,
+) -> Result<impl Responder, SettingsError> {
+    let (side, id) = path.into_inner();
+    let mut settings = settings_rx.borrow().clone();
+    let exceptions = &mut settings.side_mut(&side)?.exceptions;
+    let Some(i) = exceptions.iter().position(|e| e.id == id) else {
+        return Err(SettingsError::NoException(id));
+    };
+    exceptions.remove(i);
+    settings.drop_expired(today(&settings));
+
+    if let Err(e) = settings.save(SETTINGS_FILE) {
+        return Ok(HttpResponse::InternalServerError().body(e.to_string()));
+    }
+    if settings_tx.send(settings).is_err() {
+        return Ok(HttpResponse::InternalServerError().body("settings watch channel closed"));
+    }
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+/// The exceptions from the calendar file
+#[get("/calendar")]
+async fn get_calendar(
+    settings_rx: Data<Receiver<Settings>>,
+) -> Result<impl Responder, SettingsError> {
+    let settings = settings_rx.borrow().clone();
+    let cfg = settings
+        .calendar
+        .as_ref()
+        .ok_or(SettingsError::NoCalendar)?;
+    Ok(Json(calendar::load(cfg, &settings.timezone).await?))
+}
+
+/// The exceptions an uploaded calendar would make, without saving it
+#[post("/calendar/preview")]
+async fn post_calendar_preview(
+    settings_rx: Data<Receiver<Settings>>,
+    ics: String,
+) -> impl Responder {
+    let settings = settings_rx.borrow();
+    let cfg = settings.calendar.clone().unwrap_or_default();
+    Json(calendar::parse(&ics, &cfg, &settings.timezone))
+}
+
+/// Replaces the calendar file, which the scheduler picks up right away
+#[post("/calendar")]
+async fn post_calendar(
+    settings_rx: Data<Receiver<Settings>>,
+    settings_tx: Data<Sender<Settings>>,
+    ics: String,
+) -> Result<impl Responder, SettingsError> {
+    if !calendar::is_calendar(&ics) {
+        return Err(SettingsError::NotCalendar);
+    }
+    let file = match &settings_rx.borrow().calendar {
+        Some(cfg) => cfg.file.clone(),
+        None => return Err(SettingsError::NoCalendar),
+    };
+    fs::write(file, ics).await?;
+
+    // nothing changed, but the schedule is remade
+    settings_tx.send_modify(|_| {});
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+/// Frank's response to a command run by an action endpoint
+fn frank_response(res: CommandResult) -> HttpResponse {
+    match res {
+        Ok(()) => HttpResponse::Ok().body("ok"),
+        Err(FrankError::ExpectedOk(res)) => HttpResponse::InternalServerError().body(res),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[post("/action/prime")]
+async fn post_action_prime(frank: Data<FrankHandle>) -> impl Responder {
+    frank_response(frank.exec(FrankCommand::Prime, CMD_TIMEOUT).await)
+}
+
+#[derive(Deserialize)]
+struct TempAction {
+    side: SideTarget,
+    temp: Temp,
+    ///seconds
+    duration: u16,
+}
+
+#[post("/action/temp")]
+async fn post_action_temp(
+    frank: Data<FrankHandle>,
+    action: Json<TempAction>,
+) -> Result<impl Responder, SettingsError> {
+    let TempAction {
+        side,
+        temp,
+        duration,
+    } = action.into_inner();
+    if !temp.in_range() {
+        return Err(SettingsError::InvalidTemp(temp));
+    }
+
+    let cmd = FrankCommand::SetTemp(side, temp.level(), duration);
+    Ok(frank_response(frank.exec(cmd, CMD_TIMEOUT).await))
+}
+
+#[derive(Deserialize)]
+struct AlarmAction {
+    side: SideTarget,
+    pattern: VibrationPattern,
+    intensity: u8,
+    ///seconds
+    duration: u16,
+    ///seconds from now
+    #[serde(default = "default_alarm_delay")]
+    delay: u16,
+}
+
+fn default_alarm_delay() -> u16 {
+    5
+}
+
+/// Sets off a vibration alarm shortly, to try it out
+#[post("/action/alarm")]
+async fn post_action_alarm(
+    frank: Data<FrankHandle>,
+    settings_rx: Data<Receiver<Settings>>,
+    action: Json<AlarmAction>,
+) -> impl Responder {
+    let action = action.into_inner();
+    let alarm = VibrationAlarm {
+        pattern: action.pattern,
+        intensity: action.intensity,
+        duration: action.duration,
+        offset: 0,
+    };
+    let tz = settings_rx.borrow().timezone.clone();
+    let at = (Timestamp::now() + SignedDuration::from_secs(action.delay.into())).to_zoned(tz);
+
+    let cmd = FrankCommand::TestAlarm(action.side, Box::new((alarm, at)));
+    frank_response(frank.exec(cmd, CMD_TIMEOUT).await)
+}
+
+/// Clears the alarms for a side. Frank clears both sides,
+/// so the other side's alarm is armed again
+#[post("/action/clear_alarm")]
+async fn post_action_clear_alarm(
+    frank: Data<FrankHandle>,
+    side: Json<SideTarget>,
+) -> impl Responder {
+    let cmd = FrankCommand::ClearAlarm(side.into_inner());
+    frank_response(frank.exec(cmd, CMD_TIMEOUT).await)
+}
+
 macro_rules! define_settings_endpoints {
     (
         $(
@@ -214,6 +849,7 @@ macro_rules! define_settings_endpoints {
                 ) -> Result<impl Responder, SettingsError> {
                     let mut settings = settings_rx.borrow().clone();
                     settings.as_solo_mut()?.$field = value.into_inner();
+                    settings.validate()?;
 
                     if let Err(e) = settings.save(SETTINGS_FILE) {
                         return Ok(HttpResponse::InternalServerError().body(e.to_string()))
@@ -231,6 +867,7 @@ macro_rules! define_settings_endpoints {
                 ) -> Result<impl Responder, SettingsError> {
                     let mut settings = settings_rx.borrow().clone();
                     settings.as_couples_mut()?.0.$field = value.into_inner();
+                    settings.validate()?;
 
                     if let Err(e) = settings.save(SETTINGS_FILE) {
                         return Ok(HttpResponse::InternalServerError().body(e.to_string()))
@@ -248,6 +885,7 @@ macro_rules! define_settings_endpoints {
                 ) -> Result<impl Responder, SettingsError> {
                     let mut settings = settings_rx.borrow().clone();
                     settings.as_couples_mut()?.1.$field = value.into_inner();
+                    settings.validate()?;
 
                     if let Err(e) = settings.save(SETTINGS_FILE) {
                         return Ok(HttpResponse::InternalServerError().body(e.to_string()))
@@ -275,9 +913,11 @@ macro_rules! define_settings_endpoints {
 }
 
 define_settings_endpoints!(
-    temp_profile: Vec<i16>,
+    temp_profile: Vec<ProfilePoint>,
+    ramp: Option<Ramp>,
     wake: Time,
     sleep: Time,
     vibration: Option<VibrationAlarm>,
     heat: Option<HeatAlarm>,
+    weekly: BTreeMap<Weekday, DaySettings>,
 );
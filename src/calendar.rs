@@ -0,0 +1,354 @@
+//! Schedule exceptions from an iCalendar (`.ics`) file.
+//!
+//! Only enough of iCalendar is understood to find events: each `VEVENT`'s
+//! `SUMMARY`, `CATEGORIES`, `DTSTART` and `DTEND`. Recurring events only
+//! count for their first occurrence, and events that can't be read are skipped.
+
+use std::{
+    io,
+    time::{Duration, SystemTime},
+};
+
+use jiff::{
+    civil::{Date, DateTime},
+    tz::TimeZone,
+    SignedDuration, ToSpan, Zoned,
+};
+use log::warn;
+use serde::Serialize;
+use tokio::{fs, time::sleep};
+
+use crate::settings::{CalendarSettings, ScheduleException};
+
+/// How often to look for changes to the calendar file
+const CHECK_INT: Duration = Duration::from_secs(60);
+
+/// A content line's parameters, as (NAME, value)
+type Params = Vec<(String, String)>;
+
+/// A schedule exception and the event it came from
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CalendarException {
+    pub summary: String,
+    #[serde(flatten)]
+    pub exception: ScheduleException,
+}
+
+#[derive(Debug, Default)]
+struct Event {
+    summary: String,
+    categories: Vec<String>,
+    start: Option<Zoned>,
+    end: Option<Zoned>,
+    /// a date without a time
+    all_day: bool,
+}
+
+/// Reads `cfg.file`, see [`parse`]
+pub async fn load(cfg: &CalendarSettings, tz: &TimeZone) -> io::Result<Vec<CalendarException>> {
+    Ok(parse(&fs::read_to_string(&cfg.file).await?, cfg, tz))
+}
+
+/// The exceptions for the events in `ics` that match `cfg`'s keywords
+pub fn parse(ics: &str, cfg: &CalendarSettings, tz: &TimeZone) -> Vec<CalendarException> {
+    events(ics, tz)
+        .iter()
+        .filter_map(|event| exception(event, cfg))
+        .collect()
+}
+
+/// Whether `ics` is an iCalendar file, a `VCALENDAR` from start to end
+pub fn is_calendar(ics: &str) -> bool {
+    let lines = unfold(ics);
+    let mut lines = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| split_line(line));
+    let is = |line: Option<(String, Params, &str)>, name: &str| {
+        line.is_some_and(|(n, _, value)| n == name && value.trim() == "VCALENDAR")
+    };
+    is(lines.next(), "BEGIN") && is(lines.next_back(), "END")
+}
+
+/// When the file was last modified, if it can be read
+pub async fn modified(file: &str) -> Option<SystemTime> {
+    fs::metadata(file).await.and_then(|m| m.modified()).ok()
+}
+
+/// Resolves once `file` was modified at some time other than `since`
+pub async fn changed(file: &str, since: Option<SystemTime>) {
+    loop {
+        sleep(CHECK_INT).await;
+        if modified(file).await != since {
+            return;
+        }
+    }
+}
+
+fn events(ics: &str, tz: &TimeZone) -> Vec<Event> {
+    let mut res = Vec::new();
+    let mut event: Option<Event> = None;
+    // components inside the event, like VALARM
+    let mut nested = 0;
+
+    for line in unfold(ics) {
+        let Some((name, params, value)) = split_line(&line) else {
+            continue;
+        };
+        let Some(cur) = &mut event else {
+            if name == "BEGIN" && value == "VEVENT" {
+                event = Some(Event::default());
+            }
+            continue;
+        };
+
+        match name.as_str() {
+            "BEGIN" => nested += 1,
+            "END" if nested > 0 => nested -= 1,
+            "END" => match event.take() {
+                Some(Event {
+                    start: None,
+                    summary,
+                    ..
+                }) => {
+                    warn!("[Calendar] Skipping event without a start: {summary:?}");
+                }
+                Some(done) => res.push(done),
+                None => {}
+            },
+            _ if nested > 0 => {}
+            "SUMMARY" => cur.summary = unescape(value),
+            "CATEGORIES" => cur.categories.extend(split_list(value)),
+            "DTSTART" => match parse_when(&params, value, tz) {
+                Some((start, all_day)) => {
+                    cur.start = Some(start);
+                    cur.all_day = all_day;
+                }
+                None => warn!("[Calendar] Could not read DTSTART `{value}`"),
+            },
+            "DTEND" => match parse_when(&params, value, tz) {
+                Some((end, _)) => cur.end = Some(end),
+                None => warn!("[Calendar] Could not read DTEND `{value}`"),
+            },
+            _ => {}
+        }
+    }
+
+    res
+}
+
+fn exception(event: &Event, cfg: &CalendarSettings) -> Option<CalendarException> {
+    let start = event.start.as_ref()?;
+    let exception = if matches(event, &cfg.away) {
+        // every night that starts during the event
+        let end = match &event.end {
+            Some(end) => end.clone(),
+            None if event.all_day => start.checked_add(1.day()).ok()?,
+            None => start.clone(),
+        };
+        let (from, to) = (start.date().tomorrow().ok()?, end.date());
+        if to < from {
+            return None;
+        }
+        ScheduleException {
+            id: 0,
+            from,
+            to: (to != from).then_some(to),
+            skip: true,
+            sleep: None,
+            wake: None,
+        }
+    } else if matches(event, &cfg.early) && !event.all_day {
+        let wake = start
+            .checked_sub(SignedDuration::from_secs(cfg.wake_before.into()))
+            .ok()?;
+        ScheduleException {
+            id: 0,
+            from: wake.date(),
+            to: None,
+            skip: false,
+            sleep: None,
+            wake: Some(wake.time()),
+        }
+    } else {
+        return None;
+    };
+
+    Some(CalendarException {
+        summary: event.summary.clone(),
+        exception,
+    })
+}
+
+/// Whether the event's summary contains one of `keywords`, or has one as a category
+fn matches(event: &Event, keywords: &[String]) -> bool {
+    let summary = event.summary.to_lowercase();
+    keywords
+        .iter()
+        .filter(|k| !k.is_empty())
+        .map(|k| k.to_lowercase())
+        .any(|k| summary.contains(&k) || event.categories.iter().any(|c| c.to_lowercase() == k))
+}
+
+/// Joins lines that were folded onto the next, which start with whitespace
+fn unfold(ics: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in ics.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// Splits `NAME;PARAM=VALUE:value` into its name, parameters and value
+fn split_line(line: &str) -> Option<(String, Params, &str)> {
+    let mut quoted = false;
+    let (colon, _) = line.char_indices().find(|&(_, c)| {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        c == ':' && !quoted
+    })?;
+
+    let mut head = line[..colon].split(';');
+    let name = head.next()?.to_ascii_uppercase();
+    let params = head
+        .filter_map(|param| param.split_once('='))
+        .map(|(k, v)| (k.to_ascii_uppercase(), v.trim_matches('"').to_string()))
+        .collect();
+    Some((name, params, &line[colon + 1..]))
+}
+
+/// A `DTSTART` or `DTEND` in `tz`, and whether it is a whole day
+fn parse_when(params: &[(String, String)], value: &str, tz: &TimeZone) -> Option<(Zoned, bool)> {
+    let param = |name| params.iter().find(|(k, _)| k == name).map(|(_, v)| v);
+    if param("VALUE").is_some_and(|v| v == "DATE") || value.len() == 8 {
+        let date = Date::strptime("%Y%m%d", value).ok()?;
+        return Some((date.to_zoned(tz.clone()).ok()?, true));
+    }
+
+    let (value, utc) = match value.strip_suffix('Z') {
+        Some(value) => (value, true),
+        None => (value, false),
+    };
+    let dt = DateTime::strptime("%Y%m%dT%H%M%S", value).ok()?;
+    let event_tz = match param("TZID") {
+        _ if utc => TimeZone::UTC,
+        // anything jiff doesn't know (like Windows names) is taken as local
+        Some(tzid) => TimeZone::get(tzid).unwrap_or_else(|_| tz.clone()),
+        // floating, the same wall clock time anywhere
+        None => tz.clone(),
+    };
+    let at = dt.to_zoned(event_tz).ok()?;
+    Some((at.with_time_zone(tz.clone()), false))
+}
+
+fn unescape(text: &str) -> String {
+    let mut res = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, c == '\\') {
+            (_, true) => match chars.next() {
+                Some('n' | 'N') => res.push('\n'),
+                Some(escaped) => res.push(escaped),
+                None => {}
+            },
+            (c, false) => res.push(c),
+        }
+    }
+    res
+}
+
+/// Splits a comma separated list, keeping escaped commas
+fn split_list(text: &str) -> Vec<String> {
+    let mut items = vec![String::new()];
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let item = items.last_mut().unwrap();
+        match c {
+            '\\' => {
+                item.push(c);
+                item.extend(chars.next());
+            }
+            ',' => items.push(String::new()),
+            c => item.push(c),
+        }
+    }
+    items.iter().map(|item| unescape(item.trim())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use jiff::{
+        civil::{date, time},
+        tz::TimeZone,
+    };
+
+    use crate::settings::{CalendarSettings, ScheduleException};
+
+    use super::{is_calendar, parse, CalendarException};
+
+    fn exception(summary: &str, exception: ScheduleException) -> CalendarException {
+        CalendarException {
+            summary: summary.to_string(),
+            exception,
+        }
+    }
+
+    #[test]
+    fn test_fixture() {
+        let cfg = CalendarSettings {
+            away: vec!["trip".to_string(), "away".to_string()],
+            ..Default::default()
+        };
+        let tz = TimeZone::get("America/New_York").unwrap();
+        let res = parse(include_str!("../fixtures/calendar.ics"), &cfg, &tz);
+
+        let skip = |from, to| ScheduleException {
+            id: 0,
+            from,
+            to,
+            skip: true,
+            sleep: None,
+            wake: None,
+        };
+        let wake = |from, wake| ScheduleException {
+            id: 0,
+            from,
+            to: None,
+            skip: false,
+            sleep: None,
+            wake: Some(wake),
+        };
+        assert_eq!(
+            res,
+            vec![
+                // all day, from the 10th through the 17th
+                exception(
+                    "Trip to Lisbon",
+                    skip(date(2026, 11, 11), Some(date(2026, 11, 18)))
+                ),
+                // Berlin time, an hour ahead of the meeting
+                exception(
+                    "Early standup with the Berlin office, bring the slides",
+                    wake(date(2026, 11, 3), time(2, 30, 0, 0))
+                ),
+                // by category, from UTC
+                exception("Flight home", wake(date(2026, 11, 20), time(4, 15, 0, 0))),
+                // one night away
+                exception("Cabin, no signal", skip(date(2026, 12, 6), None)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_calendar() {
+        assert!(is_calendar(include_str!("../fixtures/calendar.ics")));
+        assert!(is_calendar("\r\nbegin:VCALENDAR\r\nEND:VCALENDAR\r\n"));
+        assert!(!is_calendar("BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\n"));
+        assert!(!is_calendar(r#"{ "not": "a calendar" }"#));
+        assert!(!is_calendar(""));
+    }
+}
@@ -0,0 +1,167 @@
+//! The alarms Frank has armed for each side.
+//!
+//! `ALARM_CLEAR` always clears both sides. When only one side meant to
+//! clear its alarm, the other side's alarm is armed again afterwards.
+//! A test alarm replaces the side's alarm, so that one is armed again
+//! once the test is over.
+
+use jiff::{SignedDuration, Timestamp, Zoned};
+use log::info;
+
+use crate::settings::VibrationAlarm;
+
+use super::command::{FrankCommand, SideTarget};
+
+#[derive(Debug, Default)]
+pub struct ArmedAlarms {
+    left: Option<(VibrationAlarm, Zoned)>,
+    right: Option<(VibrationAlarm, Zoned)>,
+}
+
+impl ArmedAlarms {
+    /// Records a command Frank ran, returning the commands needed to re-arm
+    /// whatever it cleared or replaced that it shouldn't have, and when
+    pub fn ran(&mut self, cmd: &FrankCommand, now: Timestamp) -> Vec<(Timestamp, FrankCommand)> {
+        match cmd {
+            FrankCommand::SetAlarm(side, alarm) => {
+                self.arm(side, alarm);
+                vec![]
+            }
+            FrankCommand::TestAlarm(side, alarm) => {
+                let over = alarm_end(alarm);
+                let replaced = [
+                    (SideTarget::Left, &self.left),
+                    (SideTarget::Right, &self.right),
+                ];
+                let restore = replaced
+                    .into_iter()
+                    .filter(|(armed_side, _)| side.covers(armed_side))
+                    .filter_map(|(armed_side, armed)| Some((armed_side, armed.clone()?)))
+                    .filter(|(_, armed)| !is_over(armed, over))
+                    .map(|(side, armed)| {
+                        info!(
+                            "[Frank] Re-arming {side:?} alarm for {} after the test",
+                            armed.1
+                        );
+                        (over, FrankCommand::SetAlarm(side, Box::new(armed)))
+                    })
+                    .collect();
+                self.arm(side, alarm);
+                restore
+            }
+            FrankCommand::ClearAlarm(cleared) => {
+                let sides = [
+                    (SideTarget::Left, self.left.take()),
+                    (SideTarget::Right, self.right.take()),
+                ];
+                sides
+                    .into_iter()
+                    .filter(|(side, _)| !cleared.covers(side))
+                    .filter_map(|(side, alarm)| alarm.map(|alarm| (side, alarm)))
+                    .filter(|(_, alarm)| !is_over(alarm, now))
+                    .map(|(side, alarm)| {
+                        info!("[Frank] Re-arming {side:?} alarm for {}", alarm.1);
+                        (now, FrankCommand::SetAlarm(side, Box::new(alarm)))
+                    })
+                    .collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    fn arm(&mut self, side: &SideTarget, alarm: &(VibrationAlarm, Zoned)) {
+        if side.cont_left() {
+            self.left = Some(alarm.clone());
+        }
+        if side.cont_right() {
+            self.right = Some(alarm.clone());
+        }
+    }
+}
+
+/// When an alarm finishes going off
+fn alarm_end((alarm, at): &(VibrationAlarm, Zoned)) -> Timestamp {
+    at.timestamp() + SignedDuration::from_secs(alarm.duration.into())
+}
+
+/// Whether an alarm has finished going off
+fn is_over(alarm: &(VibrationAlarm, Zoned), now: Timestamp) -> bool {
+    alarm_end(alarm) <= now
+}
+
+#[cfg(test)]
+mod tests {
+    use jiff::{SignedDuration, Timestamp, Zoned};
+
+    use crate::{
+        frank::command::{FrankCommand, SideTarget},
+        settings::{VibrationAlarm, VibrationPattern},
+    };
+
+    use super::ArmedAlarms;
+
+    fn alarm(at: &str) -> Box<(VibrationAlarm, Zoned)> {
+        let alarm = VibrationAlarm {
+            pattern: VibrationPattern::Rise,
+            intensity: 50,
+            duration: 600,
+            offset: 0,
+        };
+        Box::new((alarm, at.parse().unwrap()))
+    }
+
+    fn set_alarm(side: SideTarget, at: &str) -> FrankCommand {
+        FrankCommand::SetAlarm(side, alarm(at))
+    }
+
+    #[test]
+    fn test_rearm_partner() {
+        let now: Timestamp = "2025-06-02T10:00:00Z".parse().unwrap();
+        let mut alarms = ArmedAlarms::default();
+        let left = set_alarm(SideTarget::Left, "2025-06-02T11:00:00Z[UTC]");
+        let right = set_alarm(SideTarget::Right, "2025-06-02T12:00:00Z[UTC]");
+        assert!(alarms.ran(&left, now).is_empty());
+        assert!(alarms.ran(&right, now).is_empty());
+
+        // clearing the left side brings back the right
+        let rearm = alarms.ran(&FrankCommand::ClearAlarm(SideTarget::Left), now);
+        assert_eq!(rearm, vec![(now, right.clone())]);
+        alarms.ran(&rearm[0].1, now);
+
+        // only until it has gone off
+        let later = now + SignedDuration::from_mins(125);
+        let rearm = alarms.ran(&FrankCommand::ClearAlarm(SideTarget::Left), later);
+        assert_eq!(rearm, vec![(later, right)]);
+        alarms.ran(&rearm[0].1, now);
+        let later = now + SignedDuration::from_mins(131);
+        assert!(alarms
+            .ran(&FrankCommand::ClearAlarm(SideTarget::Left), later)
+            .is_empty());
+
+        // clearing both leaves nothing to re-arm
+        alarms.ran(&left, now);
+        assert!(alarms
+            .ran(&FrankCommand::ClearAlarm(SideTarget::Both), now)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_restore_after_test() {
+        let now: Timestamp = "2025-06-02T10:00:00Z".parse().unwrap();
+        let mut alarms = ArmedAlarms::default();
+        let left = set_alarm(SideTarget::Left, "2025-06-02T11:00:00Z[UTC]");
+        let right = set_alarm(SideTarget::Right, "2025-06-02T12:00:00Z[UTC]");
+        alarms.ran(&left, now);
+        alarms.ran(&right, now);
+
+        // the left alarm comes back once the test has gone off for 10 minutes
+        let test = FrankCommand::TestAlarm(SideTarget::Left, alarm("2025-06-02T10:00:05Z[UTC]"));
+        let restore = alarms.ran(&test, now);
+        let over: Timestamp = "2025-06-02T10:10:05Z".parse().unwrap();
+        assert_eq!(restore, vec![(over, left)]);
+
+        // the right side is still armed, not the test
+        let rearm = alarms.ran(&FrankCommand::ClearAlarm(SideTarget::Left), now);
+        assert_eq!(rearm, vec![(now, right)]);
+    }
+}
@@ -1,42 +1,44 @@
-use jiff::{civil::Time, tz::TimeZone};
-use log::{error, info};
-use tokio::net::UnixStream;
+use jiff::{Timestamp, Zoned};
+use log::{error, info, warn};
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncWrite};
 
-use crate::{
-    frank::socket::{cbor_transaction, i16_transaction, u16_transaction},
-    settings::VibrationAlarm,
-};
+use crate::settings::VibrationAlarm;
 
 use super::{
     error::FrankError,
-    socket::{cmd_transaction, read_response, write_cmd_for_no_payload},
+    socket::FrankSocket,
     state::{FrankSettings, FrankState},
 };
 
-const HELLO: u8 = 0;
-const ALARM_LEFT: u8 = 5;
-const ALARM_RIGHT: u8 = 6;
-const SET_SETTINGS: u8 = 8;
-const TEMP_DUR_LEFT: u8 = 9;
-const TEMP_DUR_RIGHT: u8 = 10;
-const TEMP_LEFT: u8 = 11;
-const TEMP_RIGHT: u8 = 12;
-const PRIME: u8 = 13;
-const STATUS: u8 = 14;
-const ALARM_CLEAR: u8 = 16;
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-#[allow(dead_code)]
+pub(crate) const HELLO: u8 = 0;
+pub(crate) const ALARM_LEFT: u8 = 5;
+pub(crate) const ALARM_RIGHT: u8 = 6;
+pub(crate) const SET_SETTINGS: u8 = 8;
+pub(crate) const TEMP_DUR_LEFT: u8 = 9;
+pub(crate) const TEMP_DUR_RIGHT: u8 = 10;
+pub(crate) const TEMP_LEFT: u8 = 11;
+pub(crate) const TEMP_RIGHT: u8 = 12;
+pub(crate) const PRIME: u8 = 13;
+pub(crate) const STATUS: u8 = 14;
+pub(crate) const ALARM_CLEAR: u8 = 16;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum FrankCommand {
     Prime,
-    ClearAlarm,
-    SetAlarm(SideTarget, Box<(VibrationAlarm, Time, TimeZone)>),
+    /// Frank always clears both sides, the other side is re-armed after
+    ClearAlarm(SideTarget),
+    /// side, (alarm, when it goes off)
+    SetAlarm(SideTarget, Box<(VibrationAlarm, Zoned)>),
+    /// Like SetAlarm, but the alarm it replaces is set again once it's over
+    TestAlarm(SideTarget, Box<(VibrationAlarm, Zoned)>),
     /// side, temp, duration (seconds)
     SetTemp(SideTarget, i16, u16),
     SetSettings(Box<FrankSettings>),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SideTarget {
     Left,
     Right,
@@ -44,47 +46,50 @@ pub enum SideTarget {
 }
 
 impl FrankCommand {
-    pub async fn exec(self, stream: &mut UnixStream) -> Result<(), FrankError> {
+    pub async fn exec<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        socket: &mut FrankSocket<S>,
+    ) -> Result<(), FrankError> {
         use FrankCommand::*;
 
-        match &self {
+        match self {
             Prime => {
                 info!("[Frank] Requesting to Prime");
-                cmd_transaction(stream, PRIME).await?;
+                socket.cmd_transaction(PRIME).await?;
             },
-            ClearAlarm => {
-                info!("[Frank] Requesting to Clear Alarm");
-                cmd_transaction(stream, ALARM_CLEAR).await?;
+            ClearAlarm(side) => {
+                info!("[Frank] Requesting to Clear Alarm for {side:?}");
+                socket.cmd_transaction(ALARM_CLEAR).await?;
             }
-            SetAlarm(side, bx) => {
-                let (alarm, time, tz) = *bx.clone();
-                info!("[Frank] Requesting Alarm at {time}");
-                let cbor = alarm.stamp(time, tz).to_cbor()?;
+            SetAlarm(side, bx) | TestAlarm(side, bx) => {
+                let (alarm, at) = &**bx;
+                info!("[Frank] Requesting Alarm at {at}");
+                let cbor = alarm.stamp(at.timestamp()).to_cbor()?;
 
                 if side.cont_left() {
-                    cbor_transaction(stream, ALARM_LEFT, &cbor).await?;
+                    socket.cbor_transaction(ALARM_LEFT, &cbor).await?;
                 }
 
                 if side.cont_right() {
-                    cbor_transaction(stream, ALARM_RIGHT, &cbor).await?;
+                    socket.cbor_transaction(ALARM_RIGHT, &cbor).await?;
                 }
             }
             SetTemp(side, temp, duration) => {
                 if side.cont_left() {
                     info!("[Frank] Left Temp {temp} for {duration} seconds");
-                    u16_transaction(stream, TEMP_DUR_LEFT, *duration).await?;
-                    i16_transaction(stream, TEMP_LEFT, *temp).await?;
+                    socket.u16_transaction(TEMP_DUR_LEFT, *duration).await?;
+                    socket.i16_transaction(TEMP_LEFT, *temp).await?;
                 }
 
                 if side.cont_right() {
                     info!("[Frank] Right Temp {temp} for {duration} seconds");
-                    u16_transaction(stream, TEMP_DUR_RIGHT, *duration).await?;
-                    i16_transaction(stream, TEMP_RIGHT, *temp).await?;
+                    socket.u16_transaction(TEMP_DUR_RIGHT, *duration).await?;
+                    socket.i16_transaction(TEMP_RIGHT, *temp).await?;
                 }
             }
             SetSettings(settings) => {
                 info!("[Frank] Setting new settings to {settings:#?}");
-                cbor_transaction(stream, SET_SETTINGS, &settings.to_cbor()?).await?
+                socket.cbor_transaction(SET_SETTINGS, &settings.to_cbor()?).await?
             }
         }
 
@@ -92,12 +97,34 @@ impl FrankCommand {
     }
 }
 
+impl FrankCommand {
+    /// Whether running this command makes running `older` pointless
+    pub fn supersedes(&self, older: &FrankCommand) -> bool {
+        use FrankCommand::*;
+
+        match (self, older) {
+            (Prime, Prime) => true,
+            (
+                ClearAlarm(side),
+                ClearAlarm(old_side) | SetAlarm(old_side, _) | TestAlarm(old_side, _),
+            ) => side.covers(old_side),
+            (
+                SetAlarm(side, _) | TestAlarm(side, _),
+                SetAlarm(old_side, _) | TestAlarm(old_side, _),
+            ) => side.covers(old_side),
+            (SetTemp(side, ..), SetTemp(old_side, ..)) => side.covers(old_side),
+            (SetSettings(_), SetSettings(_)) => true,
+            _ => false,
+        }
+    }
+}
+
 /// Says hi a new Frank. If they are unfriendly it returns None
-pub async fn greet(mut stream: UnixStream) -> Option<UnixStream> {
-    match cmd_transaction(&mut stream, HELLO).await {
+pub async fn greet(mut socket: FrankSocket) -> Option<FrankSocket> {
+    match socket.cmd_transaction(HELLO).await {
         Ok(_) => {
             info!("[Frank] New Frank accepted");
-            Some(stream)
+            Some(socket)
         },
         Err(e) => {
             error!("[Frank] Unexpected HELLO response: {e}");
@@ -106,37 +133,41 @@ pub async fn greet(mut stream: UnixStream) -> Option<UnixStream> {
     }
 }
 
-/// Requests a status update from Frank,
-/// returning the parsed result if successful
-pub async fn request_new_state(stream: &mut UnixStream) -> Option<FrankState> {
-    if let Err(e) = write_cmd_for_no_payload(stream, STATUS).await {
+/// Requests a status update from Frank, returning the parsed result
+/// if successful. Anything Frank left out is kept from `prev`.
+pub async fn request_new_state<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: &mut FrankSocket<S>,
+    prev: &FrankState,
+) -> Result<FrankState, FrankError> {
+    if let Err(e) = socket.write_request(STATUS, None).await {
         error!("[Frank] Failed to write STATUS command: {e}");
-        return None
+        return Err(e);
     }
 
-    // FrankState is usually 230-245 bytes, biggest line
-    // is setting ~57 bytes
-    let res = match read_response(stream, 260, 60).await {
+    let res = match socket.read_response().await {
         Ok(s) => s,
         Err(e) => {
             error!("[Frank] Get status update command failed: {e}");
-            return None;
-        }
-    };
-
-    let new_state = match FrankState::parse(res) {
-        Ok(state) => state,
-        Err(e) => {
-            error!("[Frank] FrankState failed to parse: {e}");
-            return None;
+            return Err(e);
         }
     };
 
-    Some(new_state)
+    let mut state = FrankState::parse(&res, prev)
+        .inspect_err(|e| error!("[Frank] FrankState failed to parse: {e}"))?;
+    if !state.errors.is_empty() {
+        warn!("[Frank] Some of FrankState failed to parse: {:?}", state.errors);
+    }
+    state.captured_at = Some(Timestamp::now());
+    Ok(state)
 }
 
 impl SideTarget {
-    fn cont_left(&self) -> bool {
+    /// Whether this side includes all of `other`
+    pub fn covers(&self, other: &SideTarget) -> bool {
+        *self == SideTarget::Both || self == other
+    }
+
+    pub fn cont_left(&self) -> bool {
         use SideTarget::*;
         match self {
             Left | Both => true,
@@ -144,7 +175,7 @@ impl SideTarget {
         }
     }
 
-    fn cont_right(&self) -> bool {
+    pub fn cont_right(&self) -> bool {
         use SideTarget::*;
         match self {
             Right | Both => true,
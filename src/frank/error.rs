@@ -19,11 +19,19 @@ pub enum FrankError {
     #[error("from hex error: `{0}`")]
     FromHex(#[from] FromHexError),
     #[error("json error: `{0}`")]
-    JSON(#[from] serde_json::Error),
+    Json(#[from] serde_json::Error),
     #[error("settings error: `{0}`")]
     Settings(#[from] SettingsError),
     #[error("tried to perform operation but frank is not listening")]
     NotConnected,
+    #[error("the frank task is not running")]
+    ChannelClosed,
+    #[error("timed out while waiting for frank to run the command")]
+    CommandTimeout,
+    #[error("dropped in favor of a newer command")]
+    Superseded,
+    #[error("failed to refresh state: `{0}`")]
+    StateRefresh(String),
     #[error("unexpected end of stream while reading response")]
     UnexpectedEndOfStream,
     #[error("timed out while waiting for response")]
@@ -34,6 +42,21 @@ pub enum FrankError {
     VarMissing(String),
     #[error("failed to parse variable `{0}`")]
     VarFailedParse(String),
+    #[error("status has none of the expected variables: `{0}`")]
+    NoKnownVars(String),
     #[error(r#"expected frank to say "ok" but got `{0}`"#)]
     ExpectedOk(String),
+    #[error("bad trace on line {0}: `{1}`")]
+    BadTrace(usize, String),
+}
+
+impl FrankError {
+    /// Whether this error means the connection
+    /// to Frank is dead and cannot be used again
+    pub fn is_disconnect(&self) -> bool {
+        matches!(
+            self,
+            FrankError::IO(_) | FrankError::UnexpectedEndOfStream | FrankError::NotConnected
+        )
+    }
 }
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{timeout_at, Instant},
+};
+
+use super::{command::FrankCommand, error::FrankError, state::FrankState};
+
+pub type CommandResult = Result<(), FrankError>;
+pub type StateResult = Result<FrankState, FrankError>;
+/// Someone waiting on a fresh state from Frank
+pub type StateWaiter = oneshot::Sender<StateResult>;
+
+/// A command queued for the Frank task, with
+/// somewhere to report how it went
+#[derive(Debug)]
+pub struct FrankRequest {
+    pub cmd: FrankCommand,
+    /// If Frank hasn't started the command by now, it is dropped
+    pub deadline: Instant,
+    /// How many times Frank has failed this command so far
+    pub failures: u8,
+    res_tx: oneshot::Sender<CommandResult>,
+}
+
+/// Cloneable handle for submitting commands to Frank
+#[derive(Debug, Clone)]
+pub struct FrankHandle {
+    tx: mpsc::UnboundedSender<FrankRequest>,
+    refresh_tx: mpsc::UnboundedSender<StateWaiter>,
+}
+
+/// The result of a submitted command, which may not have happened yet
+#[derive(Debug)]
+pub struct PendingResult {
+    rx: oneshot::Receiver<CommandResult>,
+    deadline: Instant,
+}
+
+impl FrankHandle {
+    pub fn new(
+        tx: mpsc::UnboundedSender<FrankRequest>,
+        refresh_tx: mpsc::UnboundedSender<StateWaiter>,
+    ) -> Self {
+        Self { tx, refresh_tx }
+    }
+
+    /// Queues a command without waiting. If Frank hasn't started the
+    /// command within `timeout`, or a newer command makes it pointless,
+    /// it is dropped. This is separate from (and usually much shorter
+    /// than) the time Frank himself is given to respond.
+    pub fn submit(
+        &self,
+        cmd: FrankCommand,
+        timeout: Duration,
+    ) -> Result<PendingResult, FrankError> {
+        let (req, pending) = FrankRequest::new(cmd, timeout);
+        self.tx.send(req).map_err(|_| FrankError::ChannelClosed)?;
+        Ok(pending)
+    }
+
+    /// Queues a command and waits for Frank's response, see [`Self::submit`]
+    pub async fn exec(&self, cmd: FrankCommand, timeout: Duration) -> CommandResult {
+        self.submit(cmd, timeout)?.wait().await
+    }
+
+    /// Asks Frank for his state right away. Everyone who asks while
+    /// a refresh is already waiting shares the same STATUS round trip.
+    pub async fn refresh(&self, timeout: Duration) -> StateResult {
+        let (waiter, rx) = oneshot::channel();
+        self.refresh_tx
+            .send(waiter)
+            .map_err(|_| FrankError::ChannelClosed)?;
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(res)) => res,
+            Ok(Err(_)) => Err(FrankError::ChannelClosed),
+            Err(_) => Err(FrankError::CommandTimeout),
+        }
+    }
+}
+
+impl FrankRequest {
+    pub fn new(cmd: FrankCommand, timeout: Duration) -> (Self, PendingResult) {
+        let deadline = Instant::now() + timeout;
+        let (res_tx, rx) = oneshot::channel();
+        let req = Self {
+            cmd,
+            deadline,
+            failures: 0,
+            res_tx,
+        };
+        (req, PendingResult { rx, deadline })
+    }
+
+    /// Whether the caller gave up waiting on this command
+    pub fn expired(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    pub fn respond(self, res: CommandResult) {
+        // the caller may have stopped listening, which is fine
+        let _ = self.res_tx.send(res);
+    }
+}
+
+impl PendingResult {
+    /// Waits for the result of the command, giving up at its deadline.
+    /// Timing out does not guarantee Frank didn't run the command,
+    /// only that it couldn't be confirmed in time.
+    pub async fn wait(self) -> CommandResult {
+        match timeout_at(self.deadline, self.rx).await {
+            Ok(Ok(res)) => res,
+            Ok(Err(_)) => Err(FrankError::ChannelClosed),
+            Err(_) => Err(FrankError::CommandTimeout),
+        }
+    }
+}
@@ -0,0 +1,353 @@
+//! Every state Frank reports, kept for a while.
+//!
+//! Samples are held in memory and appended to a JSON lines file so
+//! they survive restarts. Old samples are dropped from memory as new
+//! ones come in, and the file is rewritten without them now and then.
+//! The file is written by its own task, so Frank never waits on it.
+
+use std::{collections::VecDeque, path::PathBuf, sync::Arc};
+
+use jiff::{SignedDuration, Timestamp};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
+    sync::{mpsc, RwLock},
+};
+
+use crate::settings::HistorySettings;
+
+use super::{
+    command::SideTarget,
+    state::{BedTemp, FrankState},
+};
+
+/// How often the file is rewritten without expired samples
+const COMPACT_INT: SignedDuration = SignedDuration::from_hours(1);
+/// Most samples kept, about 6 days of updates every 10 seconds
+const MAX_SAMPLES: usize = 50_000;
+
+pub type HistoryLock = Arc<RwLock<History>>;
+
+#[derive(Debug, Default)]
+pub struct History {
+    samples: VecDeque<HistorySample>,
+    writer: Option<mpsc::UnboundedSender<FileWrite>>,
+    compacted_at: Option<Timestamp>,
+}
+
+/// Changes for the history file's task to make
+#[derive(Debug)]
+enum FileWrite {
+    Append(String),
+    /// Replace everything with this
+    Compact(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistorySample {
+    pub at: Timestamp,
+    pub cur_temp: BedTemp,
+    pub tar_temp: BedTemp,
+    pub water_level: bool,
+    pub priming: bool,
+}
+
+/// A [`HistorySample`] for just one side of the bed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SideSample {
+    pub at: Timestamp,
+    pub cur_temp: i16,
+    pub tar_temp: i16,
+    pub water_level: bool,
+    pub priming: bool,
+}
+
+impl History {
+    /// Loads whatever history is left in the file, skipping bad lines,
+    /// and starts the task that writes to it
+    pub async fn load(settings: &HistorySettings) -> Self {
+        let file = PathBuf::from(&settings.file);
+        let samples = match fs::read_to_string(&file).await {
+            Ok(s) => s
+                .lines()
+                .filter_map(|line| {
+                    serde_json::from_str(line)
+                        .inspect_err(|e| warn!("[History] Skipping bad sample: {e}"))
+                        .ok()
+                })
+                .collect(),
+            Err(e) => {
+                info!(
+                    "[History] Starting fresh, could not read {}: {e}",
+                    file.display()
+                );
+                VecDeque::new()
+            }
+        };
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(write_file(file, rx));
+        let mut history = Self {
+            samples,
+            writer: Some(tx),
+            compacted_at: None,
+        };
+        history.prune(settings, Timestamp::now());
+        history
+    }
+
+    /// Records a state from Frank, dropping samples older than the retention
+    pub fn push(&mut self, state: &FrankState, settings: &HistorySettings) {
+        let sample = HistorySample {
+            at: state.captured_at.unwrap_or_else(Timestamp::now),
+            cur_temp: state.cur_temp.clone(),
+            tar_temp: state.tar_temp.clone(),
+            water_level: state.water_level,
+            priming: state.priming,
+        };
+        let now = sample.at;
+
+        if settings.retention > 0 {
+            self.append(&sample);
+            self.samples.push_back(sample);
+        }
+        self.prune(settings, now);
+    }
+
+    /// Samples between `from` and `to` (inclusive)
+    pub fn query(
+        &self,
+        from: Option<Timestamp>,
+        to: Option<Timestamp>,
+    ) -> impl Iterator<Item = &HistorySample> {
+        self.samples
+            .iter()
+            .filter(move |s| from.is_none_or(|from| s.at >= from) && to.is_none_or(|to| s.at <= to))
+    }
+
+    fn append(&mut self, sample: &HistorySample) {
+        match serde_json::to_string(sample) {
+            Ok(line) => self.write(FileWrite::Append(line + "\n")),
+            Err(e) => warn!("[History] Failed to save sample: {e}"),
+        }
+    }
+
+    fn prune(&mut self, settings: &HistorySettings, now: Timestamp) {
+        // a retention reaching back before time began keeps everything
+        let oldest = now
+            .checked_sub(SignedDuration::from_hours(settings.retention.into()))
+            .ok();
+        let before = self.samples.len();
+        while self
+            .samples
+            .front()
+            .is_some_and(|s| oldest.is_some_and(|oldest| s.at < oldest))
+            || self.samples.len() > MAX_SAMPLES
+        {
+            self.samples.pop_front();
+        }
+
+        let due = self
+            .compacted_at
+            .is_none_or(|at| now.duration_since(at) >= COMPACT_INT);
+        if self.samples.len() != before && due {
+            self.compact();
+            self.compacted_at = Some(now);
+        }
+    }
+
+    /// Rewrites the file with only the samples still in memory
+    fn compact(&self) {
+        let mut out = String::new();
+        for sample in &self.samples {
+            if let Ok(line) = serde_json::to_string(sample) {
+                out.push_str(&line);
+                out.push('\n');
+            }
+        }
+        self.write(FileWrite::Compact(out));
+    }
+
+    fn write(&self, write: FileWrite) {
+        // the task only stops if the runtime is going away
+        if let Some(writer) = &self.writer {
+            let _ = writer.send(write);
+        }
+    }
+}
+
+/// Makes the changes to the history file in order, until the history is gone
+async fn write_file(file: PathBuf, mut rx: mpsc::UnboundedReceiver<FileWrite>) {
+    while let Some(write) = rx.recv().await {
+        match write {
+            FileWrite::Append(line) => {
+                let res = match OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&file)
+                    .await
+                {
+                    Ok(mut f) => f.write_all(line.as_bytes()).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = res {
+                    warn!("[History] Failed to save sample: {e}");
+                }
+            }
+            FileWrite::Compact(out) => {
+                // write then rename, so a crash can't lose the whole history
+                let tmp = file.with_extension("tmp");
+                let res = match fs::write(&tmp, out).await {
+                    Ok(()) => fs::rename(&tmp, &file).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = res {
+                    warn!("[History] Failed to compact {}: {e}", file.display());
+                }
+            }
+        }
+    }
+}
+
+impl HistorySample {
+    /// Narrows to one side, or None for both
+    pub fn side(&self, side: &SideTarget) -> Option<SideSample> {
+        let (cur_temp, tar_temp) = match side {
+            SideTarget::Left => (self.cur_temp.left, self.tar_temp.left),
+            SideTarget::Right => (self.cur_temp.right, self.tar_temp.right),
+            SideTarget::Both => return None,
+        };
+        Some(SideSample {
+            at: self.at,
+            cur_temp,
+            tar_temp,
+            water_level: self.water_level,
+            priming: self.priming,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use jiff::{SignedDuration, Timestamp};
+    use tokio::time::sleep;
+
+    use crate::{
+        frank::{
+            command::SideTarget,
+            state::{BedTemp, FrankState},
+        },
+        settings::HistorySettings,
+    };
+
+    use super::{History, MAX_SAMPLES};
+
+    fn state(at: Timestamp, left: i16) -> FrankState {
+        FrankState {
+            valid: true,
+            cur_temp: BedTemp { left, right: 0 },
+            captured_at: Some(at),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_retention_and_query() {
+        let settings = HistorySettings {
+            retention: 2,
+            file: String::new(),
+        };
+        let mut history = History::default();
+        let start: Timestamp = "2025-06-01T00:00:00Z".parse().unwrap();
+        for i in 0..6 {
+            let at = start + SignedDuration::from_mins(30 * i);
+            history.push(&state(at, i as i16), &settings);
+        }
+
+        // 02:30 is the newest, so 00:00 is more than 2 hours old
+        let all: Vec<_> = history.query(None, None).map(|s| s.cur_temp.left).collect();
+        assert_eq!(all, vec![1, 2, 3, 4, 5]);
+
+        let from = start + SignedDuration::from_mins(60);
+        let to = start + SignedDuration::from_mins(120);
+        let some: Vec<_> = history
+            .query(Some(from), Some(to))
+            .map(|s| s.side(&SideTarget::Left).unwrap().cur_temp)
+            .collect();
+        assert_eq!(some, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_max_samples() {
+        let settings = HistorySettings {
+            retention: 24 * 365,
+            file: String::new(),
+        };
+        let mut history = History::default();
+        let start: Timestamp = "2025-06-01T00:00:00Z".parse().unwrap();
+        for i in 0..MAX_SAMPLES + 10 {
+            let at = start + SignedDuration::from_secs(i as i64);
+            history.push(&state(at, 0), &settings);
+        }
+
+        assert_eq!(history.query(None, None).count(), MAX_SAMPLES);
+        let oldest = history.query(None, None).next().unwrap().at;
+        assert_eq!(oldest, start + SignedDuration::from_secs(10));
+    }
+
+    #[test]
+    fn test_huge_retention() {
+        let settings = HistorySettings {
+            retention: u32::MAX,
+            file: String::new(),
+        };
+        let mut history = History::default();
+        let start: Timestamp = "2025-06-01T00:00:00Z".parse().unwrap();
+        history.push(&state(start, 0), &settings);
+        history.push(&state(start + SignedDuration::from_hours(1), 1), &settings);
+        assert_eq!(history.query(None, None).count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_persistence() {
+        let path =
+            std::env::temp_dir().join(format!("opensleep-history-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let settings = HistorySettings {
+            retention: 24,
+            file: path.to_string_lossy().to_string(),
+        };
+
+        let mut history = History::load(&settings).await;
+        let now = Timestamp::now();
+        history.push(&state(now - SignedDuration::from_mins(5), 10), &settings);
+        history.push(&state(now, 20), &settings);
+        // written in the background
+        while std::fs::read_to_string(&path).map_or(0, |s| s.lines().count()) < 2 {
+            sleep(Duration::from_millis(10)).await;
+        }
+        std::fs::write(
+            &path,
+            std::fs::read_to_string(&path).unwrap() + "not json\n",
+        )
+        .unwrap();
+
+        let loaded = History::load(&settings).await;
+        assert_eq!(
+            loaded.query(None, None).collect::<Vec<_>>(),
+            history.query(None, None).collect::<Vec<_>>()
+        );
+
+        // nothing is kept with no retention
+        let settings = HistorySettings {
+            retention: 0,
+            ..settings
+        };
+        assert_eq!(History::load(&settings).await.query(None, None).count(), 0);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,92 +1,346 @@
 use std::{io::ErrorKind, sync::Arc, time::Duration};
 
-use command::FrankCommand;
+use alarm::ArmedAlarms;
 use error::FrankError;
+use handle::{FrankHandle, FrankRequest, StateWaiter};
+use history::{History, HistoryLock};
+use jiff::Timestamp;
 use log::info;
+use proxy::Proxy;
+use queue::CommandQueue;
+use retry::RetryQueue;
+use socket::FrankSocket;
 use state::FrankState;
+use trace::Tracer;
 use tokio::{
     fs,
-    net::{UnixListener, UnixStream},
-    sync::{mpsc, RwLock},
-    time::interval,
+    net::UnixListener,
+    sync::{mpsc, watch, RwLock},
+    time::{interval, interval_at, Instant},
 };
 
+use crate::settings::{HistorySettings, RetrySettings, Settings};
+
+mod alarm;
 pub mod command;
 pub mod error;
+pub mod handle;
+pub mod history;
+mod proxy;
+mod queue;
+mod retry;
 pub mod state;
+pub mod trace;
 pub mod vibration;
 mod socket;
+#[cfg(test)]
+pub mod sim;
 
-const SOCKET_PATH: &str = "/deviceinfo/dac.sock";
-const UPDATE_STATE_INT: Duration = Duration::from_secs(1200);
+pub const SOCKET_PATH: &str = "/deviceinfo/dac.sock";
+/// STATUS more often than this is just noise for Frank
+const MIN_UPDATE_STATE_INT: Duration = Duration::from_secs(10);
+/// How long a re-armed alarm may wait in line for Frank
+const REARM_TIMEOUT: Duration = Duration::from_secs(300);
 
 pub type FrankStateLock = Arc<RwLock<FrankState>>;
 
 /// Starts up the Frank Management process which will:
-///  1. Replace the existing Unix Socket
+///  1. Replace the existing Unix Socket at `socket_path`
 ///  2. Wait until Frank connects to us
 ///  3. Spawns a green thread to send commands, read state, and accept new Franks
-///  4. Return a channel to send commands to and a shared state
-pub async fn run() -> Result<(mpsc::Sender<FrankCommand>, FrankStateLock), FrankError> {
-    remove_socket().await?;
-    let mut listener =
-        UnixListener::bind(SOCKET_PATH).map_err(|e| FrankError::BindUnixListener(e))?;
+///  4. Return a handle to send commands with, a shared state, and its history
+pub async fn run(
+    socket_path: &str,
+    settings_rx: watch::Receiver<Settings>,
+) -> Result<(FrankHandle, FrankStateLock, HistoryLock), FrankError> {
+    remove_socket(socket_path).await?;
+    let mut listener = UnixListener::bind(socket_path).map_err(FrankError::BindUnixListener)?;
 
-    let (cmd_tx, cmd_rx) = mpsc::channel(5);
+    let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+    let (refresh_tx, refresh_rx) = mpsc::unbounded_channel();
     let state_lock = Arc::new(RwLock::new(FrankState::default()));
+    let history_settings = settings_rx.borrow().history.clone();
+    let history = Arc::new(RwLock::new(History::load(&history_settings).await));
 
     // wait until we have a valid connection
+    let trace_dir = settings_rx.borrow().trace_dir.clone();
     let stream = loop {
-        if let Some(new_stream) = accept_new_frank(&mut listener).await {
+        if let Some(new_stream) = accept_new_frank(&mut listener, trace_dir.as_deref()).await {
             break new_stream;
         }
     };
 
     info!("[Frank] Frank is ready to play!");
-    tokio::spawn(task(listener, stream, cmd_rx, state_lock.clone()));
+    tokio::spawn(task(
+        listener,
+        stream,
+        cmd_rx,
+        refresh_rx,
+        state_lock.clone(),
+        history.clone(),
+        settings_rx,
+    ));
 
-    Ok((cmd_tx, state_lock))
+    Ok((FrankHandle::new(cmd_tx, refresh_tx), state_lock, history))
 }
 
 async fn task(
     mut listener: UnixListener,
-    mut stream: UnixStream,
-    mut cmd_rx: mpsc::Receiver<FrankCommand>,
+    stream: FrankSocket,
+    mut cmd_rx: mpsc::UnboundedReceiver<FrankRequest>,
+    mut refresh_rx: mpsc::UnboundedReceiver<StateWaiter>,
     state_lock: FrankStateLock,
+    history: HistoryLock,
+    mut settings_rx: watch::Receiver<Settings>,
 ) {
     info!("[Frank] Lets crank some frank!");
-    let mut interval = interval(UPDATE_STATE_INT);
+    let mut period = update_state_int(&settings_rx.borrow());
+    let mut interval = interval(period);
+    let mut stream = Some(stream);
+    let mut queue = CommandQueue::default();
+    let mut retries = RetryQueue::default();
+    let mut alarms = ArmedAlarms::default();
+    let mut proxy = settings_rx.borrow().proxy.clone().map(Proxy::new);
+    let mut history_settings = settings_rx.borrow().history.clone();
 
     loop {
-        tokio::select! {
-            new_stream = accept_new_frank(&mut listener) => {
-                if let Some(new_stream) = new_stream {
-                    stream = new_stream;
-                }
+        // pull in everything waiting so superseded
+        // commands are dropped before Frank sees them
+        while let Ok(req) = cmd_rx.try_recv() {
+            retries.drop_superseded(&req);
+            queue.push(req);
+        }
+
+        let trace_dir = settings_rx.borrow().trace_dir.clone();
+
+        // while disconnected, leave commands queued
+        let Some(cur) = &mut stream else {
+            if let Some(new_stream) = accept_new_frank(&mut listener, trace_dir.as_deref()).await {
+                stream = Some(new_stream);
+                interval.reset_immediately();
             }
+            continue;
+        };
+
+        let req = match queue.pop() {
+            Some(req) => req,
+            None => tokio::select! {
+                new_stream = accept_new_frank(&mut listener, trace_dir.as_deref()) => {
+                    if let Some(new_stream) = new_stream {
+                        stream = Some(new_stream);
+                        interval.reset_immediately();
+                    }
+                    continue;
+                }
+
+                _ = cur.wait_closed() => {
+                    log::error!("[Frank] Frank hung up");
+                    stream = None;
+                    disconnected(&state_lock).await;
+                    continue;
+                }
+
+                Some(req) = cmd_rx.recv() => {
+                    retries.drop_superseded(&req);
+                    queue.push(req);
+                    continue;
+                }
+
+                _ = retries.next_due() => match retries.pop_due() {
+                    Some(req) => req,
+                    None => continue,
+                },
 
-            cmd = cmd_rx.recv() => {
-                if let Some(cmd) = cmd {
-                    if let Err(e) = cmd.exec(&mut stream).await {
-                        log::error!("[Frank] Error exec cmd: {e}")
+                Some(waiter) = refresh_rx.recv() => {
+                    let res = refresh_state(
+                        cur,
+                        &state_lock,
+                        &history,
+                        &history_settings,
+                        &mut refresh_rx,
+                        vec![waiter],
+                    )
+                    .await;
+                    if res.is_err_and(|e| e.is_disconnect()) {
+                        stream = None;
+                        disconnected(&state_lock).await;
                     }
+                    continue;
+                }
+
+                // first tick happens immediately
+                _ = interval.tick() => {
+                    let res = refresh_state(
+                        cur,
+                        &state_lock,
+                        &history,
+                        &history_settings,
+                        &mut refresh_rx,
+                        vec![],
+                    )
+                    .await;
+                    if res.is_err_and(|e| e.is_disconnect()) {
+                        stream = None;
+                        disconnected(&state_lock).await;
+                    }
+                    continue;
+                }
+
+                frame = proxy::next_request(&mut proxy) => {
+                    if let Some(proxy) = &mut proxy
+                        && let Err(e) = proxy.forward(frame, cur).await
+                        && e.is_disconnect()
+                    {
+                        stream = None;
+                        disconnected(&state_lock).await;
+                    }
+                    continue;
+                }
+
+                Ok(()) = settings_rx.changed() => {
+                    let (new_period, new_proxy) = {
+                        let settings = settings_rx.borrow_and_update();
+                        history_settings = settings.history.clone();
+                        (update_state_int(&settings), settings.proxy.clone())
+                    };
+                    if new_period != period {
+                        info!("[Frank] Updating state every {new_period:?}");
+                        period = new_period;
+                        interval = interval_at(Instant::now() + period, period);
+                    }
+                    if new_proxy.as_ref() != proxy.as_ref().map(Proxy::settings) {
+                        proxy = new_proxy.map(Proxy::new);
+                    }
+                    continue;
+                }
+            },
+        };
+
+        let policies = settings_rx.borrow().retry.clone();
+        let res = exec_request(req, cur, &mut queue, &mut retries, &mut alarms, &policies).await;
+        if let Err(req) = res {
+            // sent again once a new Frank shows up
+            queue.push_front(req);
+            stream = None;
+            disconnected(&state_lock).await;
+        }
+    }
+}
+
+/// Runs a request and reports the result back to the caller,
+/// unless it failed and its retry policy allows another attempt.
+/// Alarms it cleared or replaced by mistake are queued to be re-armed.
+/// If Frank disconnected, the request is handed back so
+/// it can be sent to the next Frank instead.
+async fn exec_request(
+    mut req: FrankRequest,
+    socket: &mut FrankSocket,
+    queue: &mut CommandQueue,
+    retries: &mut RetryQueue,
+    alarms: &mut ArmedAlarms,
+    policies: &RetrySettings,
+) -> Result<(), FrankRequest> {
+    if req.expired() {
+        log::warn!("[Frank] Dropping expired command {:?}", req.cmd);
+        req.respond(Err(FrankError::CommandTimeout));
+        return Ok(());
+    }
+
+    match req.cmd.exec(socket).await {
+        Ok(()) => {
+            let now = Timestamp::now();
+            for (at, cmd) in alarms.ran(&req.cmd, now) {
+                // nobody is waiting on the result
+                let wait = Duration::try_from(now.duration_until(at)).unwrap_or_default();
+                let (rearm, _) = FrankRequest::new(cmd, wait + REARM_TIMEOUT);
+                match wait.is_zero() {
+                    true => queue.push_front(rearm),
+                    // waits with the retries, so anything newer replaces it
+                    false => retries.push(Instant::now() + wait, rearm),
                 }
             }
+            req.respond(Ok(()));
+        }
+        Err(e) if e.is_disconnect() => {
+            log::error!("[Frank] Error exec cmd: {e}");
+            return Err(req);
+        }
+        Err(e) => {
+            req.failures += 1;
+            let retry_at = policies
+                .policy(&req.cmd)
+                .delay(req.failures, &e)
+                .map(|delay| Instant::now() + delay)
+                .filter(|at| *at < req.deadline);
 
-            // first tick happens immediately
-            _ = interval.tick() => {
-                if let Some(new_state) = command::request_new_state(&mut stream).await {
-                    let mut state = state_lock.write().await;
-                    *state = new_state;
+            match retry_at {
+                Some(at) => {
+                    log::warn!("[Frank] Error exec cmd, retry #{} queued: {e}", req.failures);
+                    retries.push(at, req);
+                }
+                None => {
+                    log::error!("[Frank] Error exec cmd: {e}");
+                    req.respond(Err(e));
                 }
             }
         }
     }
+
+    Ok(())
+}
+
+/// Asks Frank for his state, sharing the result with everyone
+/// who asked for a refresh before he answered
+async fn refresh_state(
+    socket: &mut FrankSocket,
+    state_lock: &FrankStateLock,
+    history: &HistoryLock,
+    history_settings: &HistorySettings,
+    refresh_rx: &mut mpsc::UnboundedReceiver<StateWaiter>,
+    mut waiters: Vec<StateWaiter>,
+) -> Result<(), FrankError> {
+    let prev = state_lock.read().await.clone();
+    let res = command::request_new_state(socket, &prev).await;
+
+    while let Ok(waiter) = refresh_rx.try_recv() {
+        waiters.push(waiter);
+    }
+
+    if let Ok(new_state) = &res {
+        let mut state = state_lock.write().await;
+        *state = new_state.clone();
+        history.write().await.push(new_state, history_settings);
+    }
+
+    for waiter in waiters {
+        let shared = match &res {
+            Ok(state) => Ok(state.clone()),
+            Err(e) => Err(FrankError::StateRefresh(e.to_string())),
+        };
+        // the caller may have stopped listening, which is fine
+        let _ = waiter.send(shared);
+    }
+
+    res.map(|_| ())
+}
+
+fn update_state_int(settings: &Settings) -> Duration {
+    Duration::from_secs(settings.state_interval.into()).max(MIN_UPDATE_STATE_INT)
+}
+
+/// Invalidates the state after losing Frank
+async fn disconnected(state_lock: &FrankStateLock) {
+    info!("[Frank] Lost Frank, waiting for a new one");
+    let mut state = state_lock.write().await;
+    state.valid = false;
+    if state.disconnected_since.is_none() {
+        state.disconnected_since = Some(Timestamp::now());
+    }
 }
 
 /// Removed the existing socket, if it exists
-async fn remove_socket() -> Result<(), FrankError> {
-    let a = fs::remove_file(SOCKET_PATH).await;
+async fn remove_socket(socket_path: &str) -> Result<(), FrankError> {
+    let a = fs::remove_file(socket_path).await;
     match a {
         Ok(_) => {
             info!("[Frank] Did not have old socket");
@@ -100,11 +354,23 @@ async fn remove_socket() -> Result<(), FrankError> {
     }
 }
 
-async fn accept_new_frank(listener: &mut UnixListener) -> Option<UnixStream> {
+/// Accepts and greets a new Frank, tracing
+/// the connection if there is a `trace_dir`
+async fn accept_new_frank(
+    listener: &mut UnixListener,
+    trace_dir: Option<&str>,
+) -> Option<FrankSocket> {
     match listener.accept().await {
         Ok((stream, _)) => {
             info!("[Frank] New Frank found in the wild");
-            command::greet(stream).await
+            let mut socket = FrankSocket::new(stream);
+            if let Some(dir) = trace_dir {
+                match Tracer::create(dir).await {
+                    Ok(tracer) => socket = socket.with_trace(tracer),
+                    Err(e) => log::error!("[Frank] Could not start trace: {e}"),
+                }
+            }
+            command::greet(socket).await
         }
         Err(e) => {
             log::error!("[Frank] New Frank did not work out: {e}");
@@ -0,0 +1,133 @@
+//! Pass-through to the stock DAC.
+//!
+//! The stock DAC is moved to another socket and we connect to it as if
+//! we were Frank. Its requests are forwarded to the real Frank in between
+//! our own commands, and his responses are sent back to it.
+
+use std::{future::pending, io, time::Duration};
+
+use log::{debug, info, warn};
+use tokio::{net::UnixStream, time::sleep};
+
+use crate::settings::ProxySettings;
+
+use super::{command::HELLO, error::FrankError, socket::FrankSocket};
+
+/// How long to wait before trying to reach the stock DAC again
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub struct Proxy {
+    settings: ProxySettings,
+    dac: Option<FrankSocket>,
+}
+
+impl Proxy {
+    pub fn new(settings: ProxySettings) -> Self {
+        info!(
+            "[Proxy] Proxying for the stock DAC at {}",
+            settings.dac_socket
+        );
+        Self {
+            settings,
+            dac: None,
+        }
+    }
+
+    pub fn settings(&self) -> &ProxySettings {
+        &self.settings
+    }
+
+    /// Resolves with the next request from the stock DAC,
+    /// (re)connecting to it as needed. Cancel safe.
+    pub async fn next_request(&mut self) -> Vec<u8> {
+        loop {
+            let Some(dac) = &mut self.dac else {
+                match connect(&self.settings.dac_socket) {
+                    Ok(stream) => {
+                        info!("[Proxy] Connected to the stock DAC");
+                        self.dac = Some(FrankSocket::new(stream));
+                    }
+                    Err(e) => {
+                        debug!("[Proxy] Stock DAC not reachable: {e}");
+                        sleep(RECONNECT_DELAY).await;
+                    }
+                }
+                continue;
+            };
+
+            match dac.read_frame().await {
+                Ok(frame) => return frame,
+                Err(e) => {
+                    warn!("[Proxy] Lost the stock DAC: {e}");
+                    self.dac = None;
+                }
+            }
+        }
+    }
+
+    /// Sends a request from the stock DAC to Frank and his response back.
+    /// If Frank failed to respond, the stock DAC is dropped (it will
+    /// greet us again on reconnect) and Frank's error is returned.
+    pub async fn forward(
+        &mut self,
+        frame: Vec<u8>,
+        frank: &mut FrankSocket,
+    ) -> Result<(), FrankError> {
+        info!("[Proxy] DAC > `{}`", frame.escape_ascii());
+        let cmd = frame
+            .split(|b| *b == b'\n')
+            .next()
+            .and_then(|cmd| std::str::from_utf8(cmd).ok())
+            .and_then(|cmd| cmd.parse::<u8>().ok());
+
+        let res = match cmd {
+            // Frank was already greeted by us
+            Some(HELLO) => "ok".to_string(),
+            Some(cmd) if self.settings.blocked.contains(&cmd) => {
+                info!("[Proxy] Blocked command {cmd}");
+                "ok".to_string()
+            }
+            Some(_) => {
+                let res = match frank.write_frame(&frame).await {
+                    Ok(()) => frank.read_response().await,
+                    Err(e) => Err(e),
+                };
+                match res {
+                    Ok(res) => res,
+                    Err(e) => {
+                        warn!("[Proxy] Frank failed forwarded request: {e}");
+                        self.dac = None;
+                        return Err(e);
+                    }
+                }
+            }
+            None => "error".to_string(),
+        };
+
+        info!("[Proxy] DAC < `{}`", res.escape_default());
+        if let Some(dac) = &mut self.dac
+            && let Err(e) = dac.write_frame(res.as_bytes()).await
+        {
+            warn!("[Proxy] Lost the stock DAC: {e}");
+            self.dac = None;
+        }
+        Ok(())
+    }
+}
+
+/// Connects without awaiting, so a connection is never half made when
+/// [`Proxy::next_request`] is cancelled. Unix sockets connect right away.
+fn connect(path: &str) -> io::Result<UnixStream> {
+    let stream = std::os::unix::net::UnixStream::connect(path)?;
+    stream.set_nonblocking(true)?;
+    UnixStream::from_std(stream)
+}
+
+/// Resolves with the next request from the stock DAC, never if not proxying
+pub async fn next_request(proxy: &mut Option<Proxy>) -> Vec<u8> {
+    match proxy {
+        Some(proxy) => proxy.next_request().await,
+        None => pending().await,
+    }
+}
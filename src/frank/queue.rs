@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+
+use log::info;
+
+use super::{error::FrankError, handle::FrankRequest};
+
+/// Commands waiting for Frank. Frank can be stuck for minutes at a time,
+/// so by the time he is free some commands may no longer matter. Those
+/// are dropped so he only gets the latest intent.
+#[derive(Debug, Default)]
+pub struct CommandQueue {
+    queue: VecDeque<FrankRequest>,
+}
+
+impl CommandQueue {
+    /// Adds a request to the back, dropping queued requests it supersedes
+    pub fn push(&mut self, req: FrankRequest) {
+        self.drop_superseded(&req);
+        self.queue.push_back(req);
+    }
+
+    /// Puts an interrupted request back at the front,
+    /// unless a newer queued request has replaced it
+    pub fn push_front(&mut self, req: FrankRequest) {
+        if self
+            .queue
+            .iter()
+            .any(|newer| newer.cmd.supersedes(&req.cmd))
+        {
+            superseded(req);
+        } else {
+            self.queue.push_front(req);
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<FrankRequest> {
+        self.queue.pop_front()
+    }
+
+    fn drop_superseded(&mut self, newer: &FrankRequest) {
+        let (old, keep) = self
+            .queue
+            .drain(..)
+            .partition(|old| newer.cmd.supersedes(&old.cmd));
+        self.queue = keep;
+        old.into_iter().for_each(superseded);
+    }
+}
+
+pub fn superseded(req: FrankRequest) {
+    info!("[Frank] Dropping superseded command {:?}", req.cmd);
+    req.respond(Err(FrankError::Superseded));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::frank::{
+        command::{FrankCommand, SideTarget},
+        error::FrankError,
+        handle::FrankRequest,
+        state::FrankSettings,
+    };
+
+    use super::CommandQueue;
+
+    fn settings(led_brightness_perc: u8) -> FrankCommand {
+        FrankCommand::SetSettings(Box::new(FrankSettings {
+            version: 1,
+            gain_left: 400,
+            gain_right: 400,
+            led_brightness_perc,
+            unknown: Default::default(),
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_coalescing() {
+        let mut queue = CommandQueue::default();
+        let mut push = |cmd| {
+            let (req, pending) = FrankRequest::new(cmd, Duration::from_secs(60));
+            queue.push(req);
+            pending
+        };
+
+        let old_left = push(FrankCommand::SetTemp(SideTarget::Left, 10, 60));
+        push(FrankCommand::SetTemp(SideTarget::Right, 20, 60));
+        let old_settings = push(settings(10));
+        push(FrankCommand::Prime);
+        push(FrankCommand::SetTemp(SideTarget::Left, 30, 60));
+        push(settings(50));
+
+        let cmds: Vec<_> = std::iter::from_fn(|| queue.pop()).map(|r| r.cmd).collect();
+        assert_eq!(
+            cmds,
+            vec![
+                FrankCommand::SetTemp(SideTarget::Right, 20, 60),
+                FrankCommand::Prime,
+                FrankCommand::SetTemp(SideTarget::Left, 30, 60),
+                settings(50),
+            ]
+        );
+
+        assert!(matches!(old_left.wait().await, Err(FrankError::Superseded)));
+        assert!(matches!(
+            old_settings.wait().await,
+            Err(FrankError::Superseded)
+        ));
+    }
+
+    #[test]
+    fn test_one_side_does_not_supersede_both() {
+        let mut queue = CommandQueue::default();
+        let (both, _) = FrankRequest::new(
+            FrankCommand::SetTemp(SideTarget::Both, 10, 60),
+            Duration::from_secs(60),
+        );
+        let (left, _) = FrankRequest::new(
+            FrankCommand::SetTemp(SideTarget::Left, 20, 60),
+            Duration::from_secs(60),
+        );
+        queue.push(both);
+        queue.push(left);
+
+        assert_eq!(
+            queue.pop().unwrap().cmd,
+            FrankCommand::SetTemp(SideTarget::Both, 10, 60)
+        );
+        assert_eq!(
+            queue.pop().unwrap().cmd,
+            FrankCommand::SetTemp(SideTarget::Left, 20, 60)
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use std::{future::pending, time::Duration};
+
+use tokio::time::{sleep_until, Instant};
+
+use crate::settings::{RetryPolicy, RetrySettings};
+
+use super::{
+    command::FrankCommand, error::FrankError, handle::FrankRequest, queue::superseded, socket,
+};
+
+/// Retrying quickly just piles more commands onto a Frank
+/// that is already struggling (which can make him seg fault)
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Failed commands waiting for their next attempt,
+/// and alarms waiting to be re-armed after a test
+#[derive(Debug, Default)]
+pub struct RetryQueue {
+    queue: Vec<(Instant, FrankRequest)>,
+}
+
+impl RetryQueue {
+    pub fn push(&mut self, at: Instant, req: FrankRequest) {
+        self.queue.push((at, req));
+    }
+
+    /// Resolves when the earliest retry is due, never if there are none
+    pub async fn next_due(&self) {
+        match self.queue.iter().map(|(at, _)| *at).min() {
+            Some(at) => sleep_until(at).await,
+            None => pending().await,
+        }
+    }
+
+    /// Drops retries that `newer` makes pointless
+    pub fn drop_superseded(&mut self, newer: &FrankRequest) {
+        let (old, keep) = self
+            .queue
+            .drain(..)
+            .partition(|(_, old)| newer.cmd.supersedes(&old.cmd));
+        self.queue = keep;
+        old.into_iter().for_each(|(_, req)| superseded(req));
+    }
+
+    /// Takes the earliest retry if it is due
+    pub fn pop_due(&mut self) -> Option<FrankRequest> {
+        let now = Instant::now();
+        let (i, _) = self
+            .queue
+            .iter()
+            .enumerate()
+            .filter(|(_, (at, _))| *at <= now)
+            .min_by_key(|(_, (at, _))| *at)?;
+        Some(self.queue.remove(i).1)
+    }
+}
+
+impl RetrySettings {
+    pub fn policy(&self, cmd: &FrankCommand) -> &RetryPolicy {
+        use FrankCommand::*;
+        match cmd {
+            Prime => &self.prime,
+            ClearAlarm(_) => &self.clear_alarm,
+            SetAlarm(..) | TestAlarm(..) => &self.set_alarm,
+            SetTemp(..) => &self.set_temp,
+            SetSettings(_) => &self.set_settings,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before retrying a command that has failed
+    /// `failures` times with `err`, or None if it shouldn't be retried
+    pub fn delay(&self, failures: u8, err: &FrankError) -> Option<Duration> {
+        if failures > self.attempts {
+            return None;
+        }
+
+        let exp = 2u32.saturating_pow(failures.saturating_sub(1).into());
+        let mut delay =
+            Duration::from_secs(self.delay.saturating_mul(exp).min(self.max_delay).into())
+                .max(MIN_RETRY_DELAY);
+
+        // a stuck Frank gets as long to recover as he got to respond
+        if matches!(err, FrankError::Timeout) {
+            delay = delay.max(socket::RESPONSE_TIMEOUT);
+        }
+
+        Some(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{frank::error::FrankError, settings::RetryPolicy};
+
+    #[test]
+    fn test_exponential_backoff() {
+        let policy = RetryPolicy {
+            attempts: 4,
+            delay: 30,
+            max_delay: 100,
+        };
+        let err = FrankError::ExpectedOk("error".to_string());
+
+        let delays: Vec<_> = (1..=5).map(|n| policy.delay(n, &err)).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_secs(30)),
+                Some(Duration::from_secs(60)),
+                Some(Duration::from_secs(100)),
+                Some(Duration::from_secs(100)),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_backoff_floor() {
+        let policy = RetryPolicy {
+            attempts: 1,
+            delay: 0,
+            max_delay: 0,
+        };
+
+        let err = FrankError::ExpectedOk("error".to_string());
+        assert_eq!(policy.delay(1, &err), Some(Duration::from_secs(10)));
+        assert_eq!(
+            policy.delay(1, &FrankError::Timeout),
+            Some(Duration::from_secs(360))
+        );
+        assert_eq!(RetryPolicy::NEVER.delay(1, &err), None);
+    }
+}
@@ -0,0 +1,354 @@
+//! A simulated Frank for end-to-end testing without a Pod.
+//!
+//! Like `frankenfirmware`, the simulator connects to the DAC socket and
+//! then answers whatever commands the DAC sends it using the same
+//! `key = value\n\n` / `ok\n\n` framing.
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{error, info};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::UnixStream,
+    task::JoinHandle,
+    time::{sleep, Instant},
+};
+
+use super::{
+    command::{
+        ALARM_CLEAR, ALARM_LEFT, ALARM_RIGHT, HELLO, PRIME, SET_SETTINGS, STATUS, TEMP_DUR_LEFT,
+        TEMP_DUR_RIGHT, TEMP_LEFT, TEMP_RIGHT,
+    },
+    state::FrankSettings,
+};
+
+/// How fast (heat levels per second) the water moves towards its target
+const HEAT_RATE: f32 = 1. / 6.;
+/// Heat level the water drifts back to when a side is off
+const AMBIENT_LEVEL: f32 = -20.;
+/// How long a prime takes
+const PRIME_DURATION: Duration = Duration::from_secs(600);
+const SENSOR_LABEL: &str = "20600-0001-F00-0001089C";
+
+#[derive(Clone)]
+pub struct FrankSim {
+    pub state: Arc<Mutex<SimState>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SimState {
+    pub left: SimSide,
+    pub right: SimSide,
+    pub water_level: bool,
+    pub priming: bool,
+    prime_left: Duration,
+    pub settings: FrankSettings,
+    /// CBOR hex of the alarm armed for each side
+    pub alarm_left: Option<String>,
+    pub alarm_right: Option<String>,
+    /// Every command received (including HELLO and STATUS), with its payload
+    pub log: Vec<(u8, Option<String>)>,
+    /// How many more times to refuse each command
+    fail: HashMap<u8, usize>,
+    last_update: Instant,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SimSide {
+    /// Kept as a float so slow heating still makes progress
+    /// between frequent STATUS requests
+    pub cur: f32,
+    pub tar: i16,
+    /// Seconds left before the side turns off
+    pub time: f32,
+    /// Duration from the last TEMP_DUR command, applied on the next TEMP
+    pending_time: u16,
+}
+
+impl FrankSim {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SimState::default())),
+        }
+    }
+
+    /// Connects to the DAC socket at `path`, retrying until it exists,
+    /// then serves commands until the DAC hangs up
+    pub fn connect(&self, path: impl AsRef<Path>) -> JoinHandle<()> {
+        let path = path.as_ref().to_path_buf();
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            let stream = loop {
+                match UnixStream::connect(&path).await {
+                    Ok(stream) => break stream,
+                    Err(_) => sleep(Duration::from_millis(10)).await,
+                }
+            };
+            info!("[FrankSim] Connected to {}", path.display());
+            if let Err(e) = serve(stream, state).await {
+                error!("[FrankSim] Connection error: {e}");
+            }
+        })
+    }
+
+    /// Makes the next `n` of `cmd` fail, like a struggling Frank
+    pub fn fail_next(&self, cmd: u8, n: usize) {
+        self.state.lock().unwrap().fail.insert(cmd, n);
+    }
+
+    /// Moves simulated time forward, on top of real time
+    pub fn advance(&self, dur: Duration) {
+        self.state.lock().unwrap().advance(dur);
+    }
+
+    pub fn snapshot(&self) -> SimState {
+        let mut state = self.state.lock().unwrap();
+        state.catch_up();
+        state.clone()
+    }
+}
+
+async fn serve(stream: UnixStream, state: Arc<Mutex<SimState>>) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    loop {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await? == 0 {
+                info!("[FrankSim] DAC hung up");
+                return Ok(());
+            }
+            if line == "\n" {
+                break;
+            }
+            lines.push(line.trim_end_matches('\n').to_string());
+        }
+
+        let Some(cmd) = lines.first().and_then(|l| l.parse::<u8>().ok()) else {
+            writer.write_all(b"error\n\n").await?;
+            continue;
+        };
+        let payload = lines.get(1).cloned();
+
+        let res = state.lock().unwrap().handle(cmd, payload);
+        writer.write_all(res.as_bytes()).await?;
+        writer.write_all(b"\n\n").await?;
+    }
+}
+
+impl SimState {
+    /// Handles one command, returning the response without the terminator
+    fn handle(&mut self, cmd: u8, payload: Option<String>) -> String {
+        self.catch_up();
+        self.log.push((cmd, payload.clone()));
+
+        if let Some(n) = self.fail.get_mut(&cmd)
+            && *n > 0
+        {
+            *n -= 1;
+            return "error".to_string();
+        }
+
+        let ok = "ok".to_string();
+        match (cmd, payload) {
+            (HELLO, None) => ok,
+            (STATUS, None) => self.status(),
+            (PRIME, None) => {
+                self.priming = true;
+                self.prime_left = PRIME_DURATION;
+                ok
+            }
+            (ALARM_CLEAR, None) => {
+                self.alarm_left = None;
+                self.alarm_right = None;
+                ok
+            }
+            (ALARM_LEFT, Some(p)) => {
+                self.alarm_left = Some(p);
+                ok
+            }
+            (ALARM_RIGHT, Some(p)) => {
+                self.alarm_right = Some(p);
+                ok
+            }
+            (SET_SETTINGS, Some(p)) => match FrankSettings::from_cbor(&p) {
+                Ok(settings) => {
+                    self.settings = settings;
+                    ok
+                }
+                Err(_) => "error".to_string(),
+            },
+            (TEMP_DUR_LEFT, Some(p)) => Self::set_dur(&mut self.left, &p),
+            (TEMP_DUR_RIGHT, Some(p)) => Self::set_dur(&mut self.right, &p),
+            (TEMP_LEFT, Some(p)) => Self::set_temp(&mut self.left, &p),
+            (TEMP_RIGHT, Some(p)) => Self::set_temp(&mut self.right, &p),
+            _ => "error".to_string(),
+        }
+    }
+
+    fn set_dur(side: &mut SimSide, payload: &str) -> String {
+        match payload.parse() {
+            Ok(dur) => {
+                side.pending_time = dur;
+                "ok".to_string()
+            }
+            Err(_) => "error".to_string(),
+        }
+    }
+
+    fn set_temp(side: &mut SimSide, payload: &str) -> String {
+        match payload.parse::<i16>() {
+            Ok(tar) if (-100..=100).contains(&tar) => {
+                side.tar = tar;
+                side.time = side.pending_time as f32;
+                "ok".to_string()
+            }
+            _ => "error".to_string(),
+        }
+    }
+
+    fn status(&self) -> String {
+        let settings = self
+            .settings
+            .to_cbor()
+            .map(|b| String::from_utf8_lossy(&b).to_uppercase())
+            .unwrap_or_default();
+
+        format!(
+            r#"tgHeatLevelR = {}
+tgHeatLevelL = {}
+heatTimeL = {}
+heatLevelL = {}
+heatTimeR = {}
+heatLevelR = {}
+sensorLabel = "{SENSOR_LABEL}"
+waterLevel = {}
+priming = {}
+settings = "{settings}""#,
+            self.right.tar,
+            self.left.tar,
+            self.left.time.ceil() as u16,
+            self.left.cur.round() as i16,
+            self.right.time.ceil() as u16,
+            self.right.cur.round() as i16,
+            self.water_level,
+            self.priming,
+        )
+    }
+
+    /// Advances by however much real time passed since the last update
+    fn catch_up(&mut self) {
+        let now = Instant::now();
+        let dur = now - self.last_update;
+        self.last_update = now;
+        self.advance(dur);
+    }
+
+    fn advance(&mut self, dur: Duration) {
+        self.left.advance(dur);
+        self.right.advance(dur);
+
+        self.prime_left = self.prime_left.saturating_sub(dur);
+        if self.prime_left.is_zero() {
+            self.priming = false;
+        }
+    }
+}
+
+impl SimSide {
+    fn advance(&mut self, dur: Duration) {
+        let secs = dur.as_secs_f32();
+
+        // heat/cool until the timer runs out, then drift to ambient
+        let active = secs.min(self.time);
+        self.cur = Self::approach(self.cur, self.tar as f32, active);
+        self.cur = Self::approach(self.cur, AMBIENT_LEVEL, secs - active);
+        self.time -= active;
+    }
+
+    fn approach(cur: f32, tar: f32, secs: f32) -> f32 {
+        let step = HEAT_RATE * secs;
+        if (tar - cur).abs() <= step {
+            tar
+        } else {
+            cur + step.copysign(tar - cur)
+        }
+    }
+}
+
+impl Default for SimState {
+    fn default() -> Self {
+        Self {
+            left: SimSide {
+                cur: AMBIENT_LEVEL,
+                ..Default::default()
+            },
+            right: SimSide {
+                cur: AMBIENT_LEVEL,
+                ..Default::default()
+            },
+            water_level: true,
+            priming: false,
+            prime_left: Duration::ZERO,
+            settings: FrankSettings {
+                version: 1,
+                gain_left: 400,
+                gain_right: 400,
+                led_brightness_perc: 100,
+                unknown: Default::default(),
+            },
+            alarm_left: None,
+            alarm_right: None,
+            log: Vec::new(),
+            fail: HashMap::new(),
+            last_update: Instant::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{SimSide, SimState, AMBIENT_LEVEL};
+
+    #[test]
+    fn test_heats_towards_target_then_drifts_back() {
+        let mut side = SimSide {
+            cur: 0.,
+            tar: 50,
+            time: 60.,
+            pending_time: 0,
+        };
+
+        side.advance(Duration::from_secs(30));
+        assert_eq!(side.cur, 5.);
+        assert_eq!(side.time, 30.);
+
+        // 30s more heating, then 60s of cooling
+        side.advance(Duration::from_secs(90));
+        assert_eq!(side.cur, 0.);
+        assert_eq!(side.time, 0.);
+
+        side.advance(Duration::from_secs(3600));
+        assert_eq!(side.cur, AMBIENT_LEVEL);
+    }
+
+    #[test]
+    fn test_temp_commands() {
+        let mut state = SimState::default();
+        assert_eq!(state.handle(9, Some("300".into())), "ok");
+        assert_eq!(state.handle(11, Some("-40".into())), "ok");
+        assert_eq!(state.handle(11, Some("500".into())), "error");
+        assert_eq!(state.left.tar, -40);
+        assert_eq!(state.left.time, 300.);
+        assert_eq!(state.right.tar, 0);
+    }
+}
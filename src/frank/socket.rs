@@ -1,128 +1,191 @@
 use std::time::Duration;
 
+use log::warn;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::UnixStream,
     time::timeout,
 };
 
-use super::error::FrankError;
+use super::{
+    error::FrankError,
+    trace::{Direction, Tracer},
+};
 
 /// sometimes Frank gets stuck, but sending more commands
 /// to him will make him seg fault so its better to wait
-const RESPONSE_TIMEOUT: Duration = Duration::from_secs(360);
-
-/// write a command, read "ok"
-pub async fn cmd_transaction(stream: &mut UnixStream, cmd: u8) -> Result<(), FrankError> {
-    write_cmd_for_no_payload(stream, cmd).await?;
-    read_ok(stream).await
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(360);
+
+/// Every request and response ends with an empty line
+pub const FRAME_END: &[u8] = b"\n\n";
+
+/// A connection to Frank. Requests are written as `COMMAND\n\n` or
+/// `COMMAND\nPAYLOAD\n\n` and responses are read as `RESPONSE\n\n`.
+/// Bytes read past the end of a response are kept for the next one.
+#[derive(Debug)]
+pub struct FrankSocket<S = UnixStream> {
+    stream: S,
+    buf: Vec<u8>,
+    trace: Option<Tracer>,
 }
 
-/// write a command then i16, read "ok"
-pub async fn i16_transaction(stream: &mut UnixStream, cmd: u8, num: i16) -> Result<(), FrankError> {
-    write_cmd_for_payload(stream, cmd).await?;
-    let mut buf = itoa::Buffer::new();
-    stream.write(buf.format(num).as_bytes()).await?;
-    write_req_end(stream).await?;
-    read_ok(stream).await
-}
+impl<S: AsyncRead + AsyncWrite + Unpin> FrankSocket<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            // FrankState is usually 230-245 bytes
+            buf: Vec::with_capacity(512),
+            trace: None,
+        }
+    }
 
-/// write a command then u16, read "ok"
-pub async fn u16_transaction(stream: &mut UnixStream, cmd: u8, num: u16) -> Result<(), FrankError> {
-    write_cmd_for_payload(stream, cmd).await?;
-    let mut buf = itoa::Buffer::new();
-    stream.write(buf.format(num).as_bytes()).await?;
-    write_req_end(stream).await?;
-    read_ok(stream).await
-}
+    /// Records every byte read or written to `trace`
+    pub fn with_trace(mut self, trace: Tracer) -> Self {
+        self.trace = Some(trace);
+        self
+    }
 
-/// write a command then cbor (in hex format, as bytes), read "ok"
-pub async fn cbor_transaction(
-    stream: &mut UnixStream,
-    cmd: u8,
-    buf: &[u8],
-) -> Result<(), FrankError> {
-    write_cmd_for_payload(stream, cmd).await?;
-    stream.write(buf).await?;
-    write_req_end(stream).await?;
-    read_ok(stream).await
-}
+    /// write a command, read "ok"
+    pub async fn cmd_transaction(&mut self, cmd: u8) -> Result<(), FrankError> {
+        self.write_request(cmd, None).await?;
+        self.read_ok().await
+    }
 
-/// write `COMMAND\n\n`
-pub async fn write_cmd_for_no_payload(stream: &mut UnixStream, cmd: u8) -> Result<(), FrankError> {
-    stream.writable().await?;
-    // stream.write(format!("{}\n\n", cmd).as_bytes()).await?;
-    let mut buf = itoa::Buffer::new();
-    stream.write(buf.format(cmd).as_bytes()).await?;
-    write_req_end(stream).await
-}
+    /// write a command then i16, read "ok"
+    pub async fn i16_transaction(&mut self, cmd: u8, num: i16) -> Result<(), FrankError> {
+        let mut buf = itoa::Buffer::new();
+        self.write_request(cmd, Some(buf.format(num).as_bytes()))
+            .await?;
+        self.read_ok().await
+    }
 
-/// write `COMMAND\n`
-async fn write_cmd_for_payload(stream: &mut UnixStream, cmd: u8) -> Result<(), FrankError> {
-    stream.writable().await?;
+    /// write a command then u16, read "ok"
+    pub async fn u16_transaction(&mut self, cmd: u8, num: u16) -> Result<(), FrankError> {
+        let mut buf = itoa::Buffer::new();
+        self.write_request(cmd, Some(buf.format(num).as_bytes()))
+            .await?;
+        self.read_ok().await
+    }
 
-    let mut buf = itoa::Buffer::new();
-    stream.write(buf.format(cmd).as_bytes()).await?;
-    stream.write_u8(b'\n').await?;
-    Ok(())
-}
+    /// write a command then cbor (in hex format, as bytes), read "ok"
+    pub async fn cbor_transaction(&mut self, cmd: u8, buf: &[u8]) -> Result<(), FrankError> {
+        self.write_request(cmd, Some(buf)).await?;
+        self.read_ok().await
+    }
 
-/// write `\n\n`
-pub async fn write_req_end(stream: &mut UnixStream) -> Result<(), FrankError> {
-    stream.write_u8(b'\n').await?;
-    stream.write_u8(b'\n').await?;
-    Ok(())
-}
+    /// write `COMMAND\n\n` or `COMMAND\nPAYLOAD\n\n` in one go
+    pub async fn write_request(
+        &mut self,
+        cmd: u8,
+        payload: Option<&[u8]>,
+    ) -> Result<(), FrankError> {
+        let mut buf = itoa::Buffer::new();
+        let mut req = buf.format(cmd).as_bytes().to_vec();
+        if let Some(payload) = payload {
+            req.push(b'\n');
+            req.extend_from_slice(payload);
+        }
+        self.write_frame(&req).await
+    }
+
+    /// write `FRAME\n\n` in one go
+    pub async fn write_frame(&mut self, frame: &[u8]) -> Result<(), FrankError> {
+        let mut buf = Vec::with_capacity(frame.len() + FRAME_END.len());
+        buf.extend_from_slice(frame);
+        buf.extend_from_slice(FRAME_END);
+
+        if let Some(trace) = &mut self.trace {
+            trace.record(Direction::ToFrank, &buf);
+        }
 
-/// read response, errors if its not "ok"
-pub async fn read_ok(stream: &mut UnixStream) -> Result<(), FrankError> {
-    let res = read_response(stream, 4, 3).await?;
-    match res.as_str() {
-        "ok" => Ok(()),
-        _ => Err(FrankError::ExpectedOk(res)),
+        self.stream.write_all(&buf).await?;
+        self.stream.flush().await?;
+        Ok(())
     }
-}
 
-/// reads a response from Frank in the format `RESPONSE\n\n`
-pub async fn read_response(stream: &mut UnixStream, exp_size: usize, max_exp_line: usize) -> Result<String, FrankError> {
-    stream.readable().await?;
-    let mut reader = BufReader::new(stream);
-    timeout(RESPONSE_TIMEOUT, async {
-        let mut result = String::with_capacity(exp_size);
-        let mut prev_ended = false;
+    /// read response, errors if its not "ok"
+    pub async fn read_ok(&mut self) -> Result<(), FrankError> {
+        let res = self.read_response().await?;
+        match res.as_str() {
+            "ok" => Ok(()),
+            _ => Err(FrankError::ExpectedOk(res)),
+        }
+    }
+
+    /// reads a response from Frank in the format `RESPONSE\n\n`,
+    /// giving him [`RESPONSE_TIMEOUT`] to answer
+    pub async fn read_response(&mut self) -> Result<String, FrankError> {
+        let frame = timeout(RESPONSE_TIMEOUT, self.read_frame())
+            .await
+            .map_err(|_| FrankError::Timeout)??;
+        Ok(String::from_utf8_lossy(&frame).into_owned())
+    }
+
+    /// Reads up to the next `\n\n`, returning everything before it.
+    /// Cancel safe, partial frames stay in the buffer.
+    pub async fn read_frame(&mut self) -> Result<Vec<u8>, FrankError> {
         loop {
-            let mut line = String::with_capacity(max_exp_line);
-            reader.read_line(&mut line).await?;
+            if let Some(pos) = self
+                .buf
+                .windows(FRAME_END.len())
+                .position(|w| w == FRAME_END)
+            {
+                let frame = self.buf[..pos].to_vec();
+                self.buf.drain(..pos + FRAME_END.len());
+                return Ok(frame);
+            }
+
+            if self.read_more().await? == 0 {
+                return Err(FrankError::UnexpectedEndOfStream);
+            }
+        }
+    }
 
-            if line == "\n" && prev_ended {
-                break;
-            } else {
-                result.push_str(&line);
+    /// Resolves once Frank hangs up. Frank never talks unless spoken to,
+    /// so anything that shows up in between commands is logged and dropped.
+    pub async fn wait_closed(&mut self) {
+        loop {
+            match self.read_more().await {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {
+                    warn!(
+                        "[Frank] Dropping unexpected data: {:?}",
+                        String::from_utf8_lossy(&self.buf)
+                    );
+                    self.buf.clear();
+                }
             }
+        }
+    }
 
-            prev_ended = line.contains('\n');
+    /// Reads whatever is available onto the end of the buffer
+    async fn read_more(&mut self) -> std::io::Result<usize> {
+        let start = self.buf.len();
+        let n = self.stream.read_buf(&mut self.buf).await?;
+        if let Some(trace) = &mut self.trace
+            && n > 0
+        {
+            trace.record(Direction::FromFrank, &self.buf[start..]);
         }
-        result.pop();
-        Ok(result)
-    })
-    .await
-    .map_err(|_| FrankError::Timeout)?
+        Ok(n)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use tokio::{
-        io::{AsyncReadExt, AsyncWriteExt},
+        io::{duplex, AsyncReadExt, AsyncWriteExt},
         net::UnixStream,
     };
 
-    use crate::frank::state::FrankSettings;
+    use crate::frank::{error::FrankError, state::FrankSettings};
 
     use super::*;
 
     #[tokio::test]
-    async fn test_write_cmd_for_no_payload() {
-        let (mut client, mut server) = UnixStream::pair().unwrap();
+    async fn test_write_request_no_payload() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut client = FrankSocket::new(client);
 
         let cmd = 14u8;
         let expected = format!("{}\n\n", cmd);
@@ -133,13 +196,14 @@ mod tests {
             assert_eq!(buf, expected.as_bytes());
         });
 
-        write_cmd_for_no_payload(&mut client, cmd).await.unwrap();
+        client.write_request(cmd, None).await.unwrap();
         server_handle.await.unwrap();
     }
 
     #[tokio::test]
     async fn test_cmd_transaction() {
-        let (mut client, mut server) = UnixStream::pair().unwrap();
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut client = FrankSocket::new(client);
 
         let cmd = 11u8;
         let expected = format!("{}\n\n", cmd);
@@ -151,13 +215,14 @@ mod tests {
             server.write_all(b"ok\n\n").await.unwrap();
         });
 
-        cmd_transaction(&mut client, cmd).await.unwrap();
+        client.cmd_transaction(cmd).await.unwrap();
         server_handle.await.unwrap();
     }
 
     #[tokio::test]
     async fn test_i16_transaction() {
-        let (mut client, mut server) = UnixStream::pair().unwrap();
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut client = FrankSocket::new(client);
 
         let cmd = 11u8;
         let payload = -10i16;
@@ -170,13 +235,14 @@ mod tests {
             server.write_all(b"ok\n\n").await.unwrap();
         });
 
-        i16_transaction(&mut client, cmd, payload).await.unwrap();
+        client.i16_transaction(cmd, payload).await.unwrap();
         server_handle.await.unwrap();
     }
 
     #[tokio::test]
     async fn test_u16_transaction() {
-        let (mut client, mut server) = UnixStream::pair().unwrap();
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut client = FrankSocket::new(client);
 
         let cmd = 11u8;
         let payload = 367u16;
@@ -189,13 +255,14 @@ mod tests {
             server.write_all(b"ok\n\n").await.unwrap();
         });
 
-        u16_transaction(&mut client, cmd, payload).await.unwrap();
+        client.u16_transaction(cmd, payload).await.unwrap();
         server_handle.await.unwrap();
     }
 
     #[tokio::test]
     async fn test_cbor_transaction() {
-        let (mut client, mut server) = UnixStream::pair().unwrap();
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut client = FrankSocket::new(client);
 
         let cmd = 11u8;
         let payload = FrankSettings {
@@ -203,6 +270,7 @@ mod tests {
             gain_left: 400,
             gain_right: 400,
             led_brightness_perc: 100,
+            unknown: Default::default(),
         }
         .to_cbor()
         .unwrap();
@@ -219,13 +287,14 @@ mod tests {
             server.write_all(b"ok\n\n").await.unwrap();
         });
 
-        cbor_transaction(&mut client, cmd, &payload).await.unwrap();
+        client.cbor_transaction(cmd, &payload).await.unwrap();
         server_handle.await.unwrap();
     }
 
     #[tokio::test]
     async fn test_read_cbor() {
-        let (mut client, mut server) = UnixStream::pair().unwrap();
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut client = FrankSocket::new(client);
 
         let expected = r#"tgHeatLevelR = 100
 tgHeatLevelL = 100
@@ -239,13 +308,85 @@ priming = false
 settings = "BF61760162676C190190626772190190626C621864FF""#;
 
         let client_handle = tokio::spawn(async move {
-            let actual = read_response(&mut client, 260, 60).await.unwrap();
+            let actual = client.read_response().await.unwrap();
             assert_eq!(expected, actual);
         });
 
         let msg = expected.to_string() + "\n\n";
-        server.write(msg.as_bytes()).await.unwrap();
+        server.write_all(msg.as_bytes()).await.unwrap();
 
         client_handle.await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_partial_frames() {
+        let (client, mut server) = duplex(64);
+        let mut client = FrankSocket::new(client);
+
+        let client_handle = tokio::spawn(async move {
+            let first = client.read_response().await.unwrap();
+            let second = client.read_response().await.unwrap();
+            (first, second)
+        });
+
+        // split in the middle of a line and between the two newlines
+        for part in ["a = 1\nb", " = 2\n", "\n", "o", "k\n", "\n"] {
+            server.write_all(part.as_bytes()).await.unwrap();
+            tokio::task::yield_now().await;
+        }
+
+        let (first, second) = client_handle.await.unwrap();
+        assert_eq!(first, "a = 1\nb = 2");
+        assert_eq!(second, "ok");
+    }
+
+    #[tokio::test]
+    async fn test_coalesced_frames() {
+        let (client, mut server) = duplex(64);
+        let mut client = FrankSocket::new(client);
+
+        // two responses arriving in one read, neither may be lost
+        server.write_all(b"ok\n\nerror\n\nok\n\n").await.unwrap();
+
+        client.read_ok().await.unwrap();
+        assert!(matches!(
+            client.read_ok().await,
+            Err(FrankError::ExpectedOk(s)) if s == "error"
+        ));
+        client.read_ok().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_eof() {
+        let (client, mut server) = duplex(64);
+        let mut client = FrankSocket::new(client);
+
+        server.write_all(b"o").await.unwrap();
+        drop(server);
+
+        assert!(matches!(
+            client.read_response().await,
+            Err(FrankError::UnexpectedEndOfStream)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_wait_closed() {
+        let (client, mut server) = duplex(64);
+        let mut client = FrankSocket::new(client);
+
+        let client_handle = tokio::spawn(async move {
+            client.wait_closed().await;
+            client
+        });
+
+        server.write_all(b"junk").await.unwrap();
+        drop(server);
+
+        let mut client = client_handle.await.unwrap();
+        assert!(matches!(
+            client.read_response().await,
+            Err(FrankError::UnexpectedEndOfStream)
+        ));
+    }
 }
@@ -1,10 +1,19 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
 
-use serde::{Deserialize, Serialize};
+use ciborium::Value;
+use jiff::Timestamp;
+use serde::{
+    de::{self, MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
 
 use super::error::FrankError;
 
-#[derive(Debug, PartialEq, Eq, Serialize, Default, Clone)]
+#[derive(Debug, PartialEq, Serialize, Default, Clone)]
 pub struct FrankState {
     /// Before Frank connects this will be false
     /// and all values will be default
@@ -23,25 +32,56 @@ pub struct FrankState {
     /// Whether the bed is priming or not
     pub priming: bool,
     pub settings: FrankSettings,
+    /// When the connection to Frank was lost, if it is currently down
+    pub disconnected_since: Option<Timestamp>,
+    /// When Frank reported this state
+    pub captured_at: Option<Timestamp>,
+    /// Variables that could not be read this time, and why.
+    /// Their fields keep the last value that could be read.
+    pub errors: BTreeMap<String, String>,
+    /// Variables Frank sent that we don't know about, unparsed
+    pub extra: BTreeMap<String, String>,
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Default, Clone)]
+const KNOWN_VARS: [&str; 10] = [
+    "tgHeatLevelR",
+    "tgHeatLevelL",
+    "heatTimeL",
+    "heatLevelL",
+    "heatTimeR",
+    "heatLevelR",
+    "sensorLabel",
+    "waterLevel",
+    "priming",
+    "settings",
+];
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Default, Clone)]
 pub struct FrankSettings {
     pub version: u8,
     pub gain_left: u16,
     pub gain_right: u16,
     pub led_brightness_perc: u8,
+    /// Whatever else Frank had in his settings
+    #[serde(skip)]
+    pub unknown: UnknownSettings,
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Default, Clone)]
+/// Settings entries we don't know about, kept as is and in Frank's
+/// order. They go back to him after the known ones.
+#[derive(Debug, PartialEq, Default, Clone)]
+pub struct UnknownSettings(pub Vec<(Value, Value)>);
+
+#[derive(Debug, PartialEq, Default, Clone)]
 struct FrankSettingsCbor {
     pub v: u8,
     pub gl: u16,
     pub gr: u16,
     pub lb: u8,
+    pub unknown: UnknownSettings,
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Default, Clone)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Default, Clone)]
 pub struct BedTemp {
     pub left: i16,
     pub right: i16,
@@ -56,31 +96,70 @@ pub struct BedTempTime {
 }
 
 impl FrankState {
-    pub fn parse(s: String) -> Result<Self, FrankError> {
+    /// Parses a STATUS response on top of `prev`, so one bad variable
+    /// doesn't throw away the rest. Only fails if none of them are there.
+    pub fn parse(s: &str, prev: &FrankState) -> Result<Self, FrankError> {
         let variables: HashMap<&str, &str> = s
             .lines()
             .filter_map(|line| line.split_once(" = "))
             .collect();
 
-        Ok(FrankState {
+        if !KNOWN_VARS.iter().any(|var| variables.contains_key(var)) {
+            return Err(FrankError::NoKnownVars(s.to_string()));
+        }
+
+        let mut state = FrankState {
             valid: true,
-            cur_temp: BedTemp {
-                left: Self::parse_var::<i16>(&variables, "heatLevelL")?,
-                right: Self::parse_var::<i16>(&variables, "heatLevelR")?,
-            },
-            tar_temp: BedTemp {
-                left: Self::parse_var::<i16>(&variables, "tgHeatLevelL")?,
-                right: Self::parse_var::<i16>(&variables, "tgHeatLevelR")?,
-            },
-            tar_temp_time: BedTempTime {
-                left: Self::parse_var::<u16>(&variables, "heatTimeL")?,
-                right: Self::parse_var::<u16>(&variables, "heatTimeR")?,
-            },
-            sensor_label: Self::get_var_string(&variables, "sensorLabel")?.to_string(),
-            water_level: Self::parse_var::<bool>(&variables, "waterLevel")?,
-            priming: Self::parse_var::<bool>(&variables, "priming")?,
-            settings: FrankSettings::from_cbor(Self::get_var_string(&variables, "settings")?)?,
-        })
+            disconnected_since: None,
+            captured_at: None,
+            errors: BTreeMap::new(),
+            extra: variables
+                .iter()
+                .filter(|(k, _)| !KNOWN_VARS.contains(k))
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ..prev.clone()
+        };
+        let errors = &mut state.errors;
+        let mut keep = |var: &str, res: Result<(), FrankError>| {
+            if let Err(e) = res {
+                errors.insert(var.to_string(), e.to_string());
+            }
+        };
+
+        let vars = &variables;
+        for (var, field) in [
+            ("heatLevelL", &mut state.cur_temp.left),
+            ("heatLevelR", &mut state.cur_temp.right),
+            ("tgHeatLevelL", &mut state.tar_temp.left),
+            ("tgHeatLevelR", &mut state.tar_temp.right),
+        ] {
+            keep(var, Self::parse_var(vars, var).map(|v| *field = v));
+        }
+        for (var, field) in [
+            ("heatTimeL", &mut state.tar_temp_time.left),
+            ("heatTimeR", &mut state.tar_temp_time.right),
+        ] {
+            keep(var, Self::parse_var(vars, var).map(|v| *field = v));
+        }
+        for (var, field) in [
+            ("waterLevel", &mut state.water_level),
+            ("priming", &mut state.priming),
+        ] {
+            keep(var, Self::parse_var(vars, var).map(|v| *field = v));
+        }
+        keep(
+            "sensorLabel",
+            Self::get_var_string(vars, "sensorLabel").map(|v| state.sensor_label = v.to_string()),
+        );
+        keep(
+            "settings",
+            Self::get_var_string(vars, "settings")
+                .and_then(FrankSettings::from_cbor)
+                .map(|v| state.settings = v),
+        );
+
+        Ok(state)
     }
 
     fn get_var_string<'a>(
@@ -102,7 +181,7 @@ impl FrankState {
         vars.get(var_name)
             .ok_or_else(|| FrankError::VarMissing(var_name.to_string()))?
             .parse()
-            .or_else(|_| Err(FrankError::VarFailedParse(var_name.to_string())))
+            .map_err(|_| FrankError::VarFailedParse(var_name.to_string()))
     }
 }
 
@@ -114,6 +193,7 @@ impl FrankSettings {
             gain_left: res.gl,
             gain_right: res.gr,
             led_brightness_perc: res.lb,
+            unknown: res.unknown,
         })
     }
 
@@ -123,11 +203,65 @@ impl FrankSettings {
             gl: self.gain_left,
             gr: self.gain_right,
             lb: self.led_brightness_perc,
+            unknown: self.unknown.clone(),
         }
         .to_cbor()
     }
 }
 
+// by hand, so the map keeps a defined length with the unknown entries in it
+impl Serialize for FrankSettingsCbor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(4 + self.unknown.0.len()))?;
+        map.serialize_entry("v", &self.v)?;
+        map.serialize_entry("gl", &self.gl)?;
+        map.serialize_entry("gr", &self.gr)?;
+        map.serialize_entry("lb", &self.lb)?;
+        for (key, value) in &self.unknown.0 {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+// by hand, since derived field names only accept text keys
+impl<'de> Deserialize<'de> for FrankSettingsCbor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct CborVisitor;
+
+        impl<'de> Visitor<'de> for CborVisitor {
+            type Value = FrankSettingsCbor;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a map of Frank's settings")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let (mut v, mut gl, mut gr, mut lb) = (None, None, None, None);
+                let mut unknown = Vec::new();
+                while let Some(key) = map.next_key::<Value>()? {
+                    match key.as_text() {
+                        Some("v") => v = Some(map.next_value()?),
+                        Some("gl") => gl = Some(map.next_value()?),
+                        Some("gr") => gr = Some(map.next_value()?),
+                        Some("lb") => lb = Some(map.next_value()?),
+                        _ => unknown.push((key, map.next_value()?)),
+                    }
+                }
+                Ok(FrankSettingsCbor {
+                    v: v.ok_or_else(|| de::Error::missing_field("v"))?,
+                    gl: gl.ok_or_else(|| de::Error::missing_field("gl"))?,
+                    gr: gr.ok_or_else(|| de::Error::missing_field("gr"))?,
+                    lb: lb.ok_or_else(|| de::Error::missing_field("lb"))?,
+                    unknown: UnknownSettings(unknown),
+                })
+            }
+        }
+
+        deserializer.deserialize_map(CborVisitor)
+    }
+}
+
 impl FrankSettingsCbor {
     pub fn from_cbor(data: &str) -> Result<Self, FrankError> {
         let bytes = hex::decode(data)?;
@@ -145,7 +279,14 @@ impl FrankSettingsCbor {
 
 #[cfg(test)]
 mod tests {
-    use crate::frank::state::{BedTemp, BedTempTime, FrankSettings, FrankState};
+    use std::collections::BTreeMap;
+
+    use ciborium::Value;
+
+    use crate::frank::{
+        error::FrankError,
+        state::{BedTemp, BedTempTime, FrankSettings, FrankState},
+    };
 
     #[test]
     fn test_settings_deserialize() {
@@ -158,6 +299,7 @@ mod tests {
                 gain_right: 400,
                 gain_left: 400,
                 led_brightness_perc: 100,
+                unknown: Default::default(),
             },
         );
     }
@@ -169,6 +311,7 @@ mod tests {
             gain_right: 400,
             gain_left: 400,
             led_brightness_perc: 100,
+            unknown: Default::default(),
         }
         .to_cbor()
         .unwrap();
@@ -185,6 +328,59 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_settings_unknown_keys() {
+        // frank's settings with an extra "xx": 5 and "fw": "ab"
+        let test = "BF61760162676C190190626772190190626C62186462787805626677626162FF";
+        let settings = FrankSettings::from_cbor(test).unwrap();
+        assert_eq!(settings.led_brightness_perc, 100);
+        assert_eq!(
+            settings.unknown.0,
+            vec![
+                (Value::Text("xx".into()), Value::Integer(5.into())),
+                (Value::Text("fw".into()), Value::Text("ab".into())),
+            ]
+        );
+
+        // changing a known key leaves the unknown ones alone
+        let changed = FrankSettings {
+            led_brightness_perc: 10,
+            ..settings.clone()
+        };
+        let cbor = String::from_utf8(changed.to_cbor().unwrap()).unwrap();
+        assert_eq!(
+            cbor,
+            "a661760162676c190190626772190190626c620a62787805626677626162"
+        );
+        let back = FrankSettings::from_cbor(&cbor).unwrap();
+        assert_eq!(back, changed);
+        assert_ne!(back, settings);
+
+        // known keys can be anywhere between the unknown ones,
+        // but are written back ahead of them
+        let test = "BF6176016278780562676C190190626C621864626772190190626677626162FF";
+        let reordered = FrankSettings::from_cbor(test).unwrap();
+        assert_eq!(reordered.unknown, settings.unknown);
+        assert_eq!(reordered.version, 1);
+        assert_eq!(
+            reordered.to_cbor().unwrap(),
+            b"a661760162676c190190626772190190626c62186462787805626677626162".to_vec()
+        );
+
+        // a non-text key and a float are kept too
+        let test = "BF61760162676C190190626772190190626C6218640705F93E00F6FF";
+        let settings = FrankSettings::from_cbor(test).unwrap();
+        assert_eq!(
+            settings.unknown.0,
+            vec![
+                (Value::Integer(7.into()), Value::Integer(5.into())),
+                (Value::Float(1.5), Value::Null),
+            ]
+        );
+        let cbor = String::from_utf8(settings.to_cbor().unwrap()).unwrap();
+        assert_eq!(FrankSettings::from_cbor(&cbor).unwrap(), settings);
+    }
+
     #[test]
     fn test_frank_state() {
         let inp = r#"tgHeatLevelR = 100
@@ -216,10 +412,90 @@ settings = "BF61760162676C190190626772190190626C621864FF""#;
                 gain_right: 400,
                 gain_left: 400,
                 led_brightness_perc: 100,
+                unknown: Default::default(),
             },
+            disconnected_since: None,
+            captured_at: None,
+            errors: BTreeMap::new(),
+            extra: BTreeMap::new(),
         };
-        let actual = FrankState::parse(inp.to_string()).unwrap();
+        let actual = FrankState::parse(inp, &FrankState::default()).unwrap();
         println!("{actual:#?}");
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_frank_state_partial() {
+        let prev = FrankState::parse(
+            r#"tgHeatLevelR = 100
+tgHeatLevelL = 100
+heatTimeL = 0
+heatLevelL = -100
+heatTimeR = 0
+heatLevelR = -100
+sensorLabel = "20600-0001-F00-0001089C"
+waterLevel = true
+priming = false
+settings = "BF61760162676C190190626772190190626C621864FF""#,
+            &FrankState::default(),
+        )
+        .unwrap();
+
+        // heatLevelR is malformed, waterLevel is missing, and two new variables
+        let inp = r#"tgHeatLevelR = 20
+tgHeatLevelL = 30
+heatTimeL = 60
+heatLevelL = -50
+heatTimeR = 60
+heatLevelR = hot
+sensorLabel = "20600-0001-F00-0001089C"
+priming = true
+settings = "BF61760162676C190190626772190190626C621864FF"
+fwVersion = "2.1.7"
+bedPresent = true"#;
+        let actual = FrankState::parse(inp, &prev).unwrap();
+
+        assert!(actual.valid);
+        assert_eq!(
+            actual.cur_temp,
+            BedTemp {
+                left: -50,
+                right: -100
+            }
+        );
+        assert_eq!(
+            actual.tar_temp,
+            BedTemp {
+                left: 30,
+                right: 20
+            }
+        );
+        assert!(actual.water_level);
+        assert!(actual.priming);
+        assert_eq!(
+            actual.errors,
+            BTreeMap::from([
+                (
+                    "heatLevelR".to_string(),
+                    "failed to parse variable `heatLevelR`".to_string()
+                ),
+                (
+                    "waterLevel".to_string(),
+                    "variable `waterLevel` is missing".to_string()
+                ),
+            ])
+        );
+        assert_eq!(
+            actual.extra,
+            BTreeMap::from([
+                ("bedPresent".to_string(), "true".to_string()),
+                ("fwVersion".to_string(), r#""2.1.7""#.to_string()),
+            ])
+        );
+
+        assert!(matches!(
+            FrankState::parse("error", &prev),
+            Err(FrankError::NoKnownVars(_))
+        ));
+    }
 }
@@ -0,0 +1,302 @@
+//! Recording and replaying everything said on the DAC socket.
+//!
+//! A trace is a text file with one line per read or write:
+//! `TIMESTAMP > BYTES` for what we sent Frank and `TIMESTAMP < BYTES`
+//! for what he sent back, with the bytes ASCII escaped (`\n`, `\xNN`).
+
+use std::{fs, path::Path};
+
+use jiff::Timestamp;
+use log::{error, info, warn};
+use tokio::{
+    fs::File,
+    io::{duplex, AsyncReadExt, AsyncWriteExt, BufWriter, DuplexStream},
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+use super::{
+    command::{self, STATUS},
+    error::FrankError,
+    socket::{FrankSocket, FRAME_END},
+    state::FrankState,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ToFrank,
+    FromFrank,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub at: Timestamp,
+    pub dir: Direction,
+    pub bytes: Vec<u8>,
+}
+
+/// A trace stops once its file is this big, which
+/// also bounds how much can wait to be written
+const MAX_TRACE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Traces one connection to Frank, handing each line
+/// to a task that writes them to the trace file
+#[derive(Debug)]
+pub struct Tracer {
+    /// gone once the trace is full
+    tx: Option<mpsc::UnboundedSender<String>>,
+    size: u64,
+}
+
+impl Direction {
+    fn marker(self) -> &'static str {
+        match self {
+            Direction::ToFrank => ">",
+            Direction::FromFrank => "<",
+        }
+    }
+}
+
+impl Tracer {
+    /// Starts a new trace file in `dir`, named after the current time
+    pub async fn create(dir: impl AsRef<Path>) -> Result<Self, FrankError> {
+        let dir = dir.as_ref();
+        tokio::fs::create_dir_all(dir).await?;
+        let path = dir.join(format!(
+            "frank-{}.trace",
+            Timestamp::now().strftime("%Y%m%dT%H%M%S%.fZ")
+        ));
+        info!("[Frank] Tracing to {}", path.display());
+        let out = BufWriter::new(File::create(path).await?);
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(write_trace(out, rx));
+        Ok(Self {
+            tx: Some(tx),
+            size: 0,
+        })
+    }
+
+    /// Hands a line to the writer without waiting on it, so a slow
+    /// trace never holds up Frank. Every line is kept until the trace
+    /// is full, after which it ends rather than leaving gaps.
+    pub fn record(&mut self, dir: Direction, bytes: &[u8]) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let line = format!(
+            "{} {} {}\n",
+            Timestamp::now(),
+            dir.marker(),
+            bytes.escape_ascii()
+        );
+        self.size += line.len() as u64;
+        if self.size > MAX_TRACE_SIZE {
+            warn!("[Frank] Trace reached {MAX_TRACE_SIZE} bytes, no longer tracing");
+            // the writer finishes what it has and stops
+            self.tx = None;
+            return;
+        }
+        // already logged why by the writer if it's gone
+        let _ = tx.send(line);
+    }
+}
+
+/// Writes trace lines until the connection is gone or the trace is full
+async fn write_trace(mut out: BufWriter<File>, mut rx: mpsc::UnboundedReceiver<String>) {
+    while let Some(line) = rx.recv().await {
+        if let Err(e) = out.write_all(line.as_bytes()).await {
+            warn!("[Frank] Failed to write trace: {e}");
+            return;
+        }
+        // written out whenever it catches up
+        if rx.is_empty()
+            && let Err(e) = out.flush().await
+        {
+            warn!("[Frank] Failed to write trace: {e}");
+            return;
+        }
+    }
+    if let Err(e) = out.flush().await {
+        warn!("[Frank] Failed to write trace: {e}");
+    }
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<TraceEntry>, FrankError> {
+    parse(&fs::read_to_string(path)?)
+}
+
+pub fn parse(trace: &str) -> Result<Vec<TraceEntry>, FrankError> {
+    trace
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(i, line)| parse_line(line).ok_or_else(|| FrankError::BadTrace(i + 1, line.into())))
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<TraceEntry> {
+    let mut parts = line.splitn(3, ' ');
+    let at = parts.next()?.parse().ok()?;
+    let dir = match parts.next()? {
+        ">" => Direction::ToFrank,
+        "<" => Direction::FromFrank,
+        _ => return None,
+    };
+    let bytes = unescape(parts.next().unwrap_or_default())?;
+    Some(TraceEntry { at, dir, bytes })
+}
+
+/// Reverses [`<[u8]>::escape_ascii`]
+fn unescape(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+        out.push(match bytes.next()? {
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'x' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            b => b,
+        });
+    }
+    Some(out)
+}
+
+/// Plays Frank's side of a trace. What the DAC writes is checked
+/// against the trace, and the stream is closed at the first difference.
+pub fn playback(entries: &[TraceEntry]) -> (DuplexStream, JoinHandle<()>) {
+    let (dac, mut frank) = duplex(1 << 16);
+    let entries = entries.to_vec();
+    let handle = tokio::spawn(async move {
+        for entry in entries {
+            match entry.dir {
+                Direction::ToFrank => {
+                    let mut buf = vec![0u8; entry.bytes.len()];
+                    if frank.read_exact(&mut buf).await.is_err() {
+                        return;
+                    }
+                    if buf != entry.bytes {
+                        error!(
+                            "[Replay] DAC sent `{}` but the trace has `{}`",
+                            buf.escape_ascii(),
+                            entry.bytes.escape_ascii()
+                        );
+                        return;
+                    }
+                }
+                Direction::FromFrank => {
+                    if frank.write_all(&entry.bytes).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+    (dac, handle)
+}
+
+/// Sends every request in the trace again, against a Frank playing back
+/// the recorded responses. STATUS responses go through the same parsing
+/// as a live Frank, and the states that parsed are returned.
+pub async fn replay(entries: &[TraceEntry]) -> Result<Vec<FrankState>, FrankError> {
+    let sent: Vec<u8> = entries
+        .iter()
+        .filter(|e| e.dir == Direction::ToFrank)
+        .flat_map(|e| e.bytes.iter().copied())
+        .collect();
+
+    let (stream, _) = playback(entries);
+    let mut socket = FrankSocket::new(stream);
+    let mut states = Vec::new();
+
+    for req in requests(&sent) {
+        let (cmd, payload) = match req.iter().position(|b| *b == b'\n') {
+            Some(i) => (&req[..i], Some(&req[i + 1..])),
+            None => (req, None),
+        };
+        let Some(cmd) = std::str::from_utf8(cmd)
+            .ok()
+            .and_then(|c| c.parse::<u8>().ok())
+        else {
+            warn!("[Replay] Skipping unknown request `{}`", req.escape_ascii());
+            continue;
+        };
+
+        if cmd == STATUS {
+            let prev = states.last().cloned().unwrap_or_default();
+            match command::request_new_state(&mut socket, &prev).await {
+                Ok(state) => {
+                    info!("[Replay] {STATUS} -> {state:?}");
+                    states.push(state);
+                }
+                Err(e) if e.is_disconnect() => return Err(e),
+                // already logged
+                Err(_) => {}
+            }
+            continue;
+        }
+
+        socket.write_request(cmd, payload).await?;
+        match socket.read_response().await {
+            Ok(res) => info!("[Replay] {cmd} -> {res}"),
+            Err(e) if e.is_disconnect() => return Err(e),
+            Err(e) => error!("[Replay] {cmd} failed: {e}"),
+        }
+    }
+
+    Ok(states)
+}
+
+/// Splits everything sent to Frank into requests
+fn requests(sent: &[u8]) -> Vec<&[u8]> {
+    let mut reqs = Vec::new();
+    let mut rest = sent;
+    while let Some(pos) = rest.windows(FRAME_END.len()).position(|w| w == FRAME_END) {
+        reqs.push(&rest[..pos]);
+        rest = &rest[pos + FRAME_END.len()..];
+    }
+    reqs
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::frank::error::FrankError;
+
+    use super::{parse, replay, unescape, Direction};
+
+    #[test]
+    fn test_escape_round_trip() {
+        let all: Vec<u8> = (0..=255).collect();
+        let escaped = all.escape_ascii().to_string();
+        assert_eq!(unescape(&escaped).unwrap(), all);
+
+        let entries = parse("2025-06-01T03:00:00Z > 14\\n\\n\n").unwrap();
+        assert_eq!(entries[0].dir, Direction::ToFrank);
+        assert_eq!(entries[0].bytes, b"14\n\n");
+        assert!(matches!(
+            parse("2025-06-01T03:00:00Z ? ok"),
+            Err(FrankError::BadTrace(1, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let trace = r#"2025-06-01T03:00:00Z > 11\n-20\n\n
+2025-06-01T03:00:01Z < ok\n\n
+2025-06-01T03:00:02Z > 14\n\n
+2025-06-01T03:00:03Z < tgHeatLevelR = 0\ntgHeatLevelL = -20\nheatTimeL = 0\nheatLevelL = -20
+2025-06-01T03:00:03Z < \nheatTimeR = 0\nheatLevelR = -20\nsensorLabel = "x"\nwaterLevel = true\npriming = false\nsettings = "BF61760162676C190190626772190190626C621864FF"\n\n
+"#;
+        let states = replay(&parse(trace).unwrap()).await.unwrap();
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].tar_temp.left, -20);
+        assert_eq!(states[0].settings.led_brightness_perc, 100);
+    }
+}
@@ -1,4 +1,4 @@
-use jiff::{civil::Time, tz::TimeZone, Timestamp};
+use jiff::Timestamp;
 use serde::{Deserialize, Serialize};
 
 use crate::settings::VibrationAlarm;
@@ -19,19 +19,13 @@ pub struct TimestampedVibrationAlarm {
 }
 
 impl VibrationAlarm {
-    pub fn stamp(&self, time: Time, tz: TimeZone) -> TimestampedVibrationAlarm {
+    /// The alarm as Frank wants it, going off `at`
+    pub fn stamp(&self, at: Timestamp) -> TimestampedVibrationAlarm {
         TimestampedVibrationAlarm {
             intensity_percent: self.intensity,
             duration_sec: self.duration,
             pattern: self.pattern.to_string(),
-            timestamp: Timestamp::now()
-                .to_zoned(tz)
-                .with()
-                .time(time)
-                .build()
-                .unwrap()
-                .timestamp()
-                .as_second() as u64,
+            timestamp: at.as_second() as u64,
         }
     }
 }
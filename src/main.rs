@@ -4,13 +4,16 @@ use scheduler::SchedulerError;
 use settings::{Settings, SettingsError};
 use simplelog::{ColorChoice, CombinedLogger, TermLogger, TerminalMode, WriteLogger};
 use thiserror::Error;
-use std::{fs::File, io};
+use std::{env, fs::File, io};
 use tokio::sync::watch;
 
 mod frank;
 mod scheduler;
 mod settings;
 mod api;
+mod calendar;
+mod profile;
+mod temp;
 
 #[cfg(test)]
 mod test;
@@ -36,6 +39,23 @@ pub enum MainError {
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), MainError> {
+    // `opensleep replay TRACE` reproduces a recorded session instead,
+    // logging to stderr so the running daemon's log file is left alone
+    if let [_, cmd, path] = env::args().collect::<Vec<_>>().as_slice()
+        && cmd == "replay"
+    {
+        TermLogger::init(
+            LevelFilter::Debug,
+            simplelog::Config::default(),
+            TerminalMode::Stderr,
+            ColorChoice::Auto,
+        )?;
+        info!("[Main] Replaying trace: {path}");
+        let states = frank::trace::replay(&frank::trace::load(path)?).await?;
+        info!("[Main] Replay finished with {} valid states", states.len());
+        return Ok(());
+    }
+
     CombinedLogger::init(vec![
         TermLogger::new(
             LevelFilter::Debug,
@@ -47,7 +67,7 @@ async fn main() -> Result<(), MainError> {
             LevelFilter::Debug,
             simplelog::Config::default(),
             File::create(LOG_FILE)
-                .map_err(|e| MainError::LogFileCreation(e))?,
+                .map_err(MainError::LogFileCreation)?,
         ),
     ])?;
 
@@ -57,13 +77,23 @@ async fn main() -> Result<(), MainError> {
     let (settings_tx, settings_rx) = watch::channel(Settings::from_file(SETTINGS_FILE)?);
 
     info!("[Main] Finding a Frank");
-    let (frank_tx, frank_state) = frank::run().await?;
+    let (frank, frank_state, history) =
+        frank::run(frank::SOCKET_PATH, settings_rx.clone()).await?;
 
     info!("[Main] Starting API server");
-    api::run(frank_state, settings_tx, settings_rx.clone()).await?;
+    api::run(
+        frank.clone(),
+        frank_state,
+        history,
+        settings_tx.clone(),
+        settings_rx.clone(),
+    )
+    .await?;
+
+    tokio::spawn(scheduler::clear_expired(settings_rx.clone(), settings_tx));
 
     info!("[Main] Starting Scheduler...");
-    scheduler::run(frank_tx, settings_rx).await?;
+    scheduler::run(frank, settings_rx).await?;
 
     Ok(())
 }
@@ -0,0 +1,239 @@
+//! Temperature profile points, spread evenly through the night or pinned to a time.
+//!
+//! Points without a time share the gap between their pinned neighbours
+//! (or sleep and wake) evenly, so a plain list of temperatures is split
+//! evenly over the whole night like it always was.
+
+use std::{fmt, str::FromStr};
+
+use jiff::{civil::Time, SignedDuration, Zoned};
+use serde::{Deserialize, Serialize};
+
+use crate::{settings::SettingsError, temp::Temp};
+
+/// A profile temperature, optionally pinned with `{"at": "sleep+0:30", "temp": -10}`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ProfilePoint {
+    At { at: Anchor, temp: Temp },
+    Even(Temp),
+}
+
+/// When a profile point starts: `sleep+0:30`, `wake-1:00` or `03:00`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Anchor {
+    Sleep(SignedDuration),
+    Wake(SignedDuration),
+    At(Time),
+}
+
+impl ProfilePoint {
+    pub fn temp(&self) -> &Temp {
+        match self {
+            ProfilePoint::At { temp, .. } | ProfilePoint::Even(temp) => temp,
+        }
+    }
+
+    pub fn anchor(&self) -> Option<&Anchor> {
+        match self {
+            ProfilePoint::At { at, .. } => Some(at),
+            ProfilePoint::Even(_) => None,
+        }
+    }
+}
+
+impl From<Temp> for ProfilePoint {
+    fn from(temp: Temp) -> Self {
+        ProfilePoint::Even(temp)
+    }
+}
+
+impl Anchor {
+    /// When this is in the night from `sleep_dt` to `wake_dt`
+    pub fn resolve(&self, sleep_dt: &Zoned, wake_dt: &Zoned) -> Result<Zoned, jiff::Error> {
+        match *self {
+            Anchor::Sleep(offset) => sleep_dt.checked_add(offset),
+            Anchor::Wake(offset) => wake_dt.checked_add(offset),
+            Anchor::At(time) => {
+                let at = sleep_dt.with().time(time).build()?;
+                match at < *sleep_dt {
+                    true => at.tomorrow(),
+                    false => Ok(at),
+                }
+            }
+        }
+    }
+
+    /// Seconds after `sleep` on a night without DST changes
+    pub fn offset(&self, sleep: Time, wake: Time) -> i64 {
+        let since_sleep = |time: Time| sleep.duration_until(time).as_secs().rem_euclid(86400);
+        match *self {
+            Anchor::Sleep(offset) => offset.as_secs(),
+            Anchor::Wake(offset) => match since_sleep(wake) {
+                // 24 hours
+                0 => 86400 + offset.as_secs(),
+                period => period + offset.as_secs(),
+            },
+            Anchor::At(time) => since_sleep(time),
+        }
+    }
+}
+
+/// Checks pinned points are in order and between `sleep` and `wake`
+pub fn validate(profile: &[ProfilePoint], sleep: Time, wake: Time) -> Result<(), SettingsError> {
+    let period = Anchor::Wake(SignedDuration::ZERO).offset(sleep, wake);
+    let mut prev = None;
+    for anchor in profile.iter().filter_map(ProfilePoint::anchor) {
+        let at = anchor.offset(sleep, wake);
+        if !(0..period).contains(&at) {
+            return Err(SettingsError::InvalidProfilePoint(
+                *anchor,
+                "outside of the sleep period",
+            ));
+        }
+        if prev.is_some_and(|prev| at <= prev) {
+            return Err(SettingsError::InvalidProfilePoint(*anchor, "out of order"));
+        }
+        prev = Some(at);
+    }
+    Ok(())
+}
+
+impl FromStr for Anchor {
+    type Err = SettingsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || SettingsError::InvalidAnchor(s.to_string());
+        let (base, offset) = match s.find(['+', '-']) {
+            Some(i) => s.split_at(i),
+            None => (s, ""),
+        };
+        let offset = match offset.is_empty() {
+            true => SignedDuration::ZERO,
+            false => parse_offset(offset).ok_or_else(invalid)?,
+        };
+
+        match base {
+            "sleep" => Ok(Anchor::Sleep(offset)),
+            "wake" => Ok(Anchor::Wake(offset)),
+            _ => s.parse().map(Anchor::At).map_err(|_| invalid()),
+        }
+    }
+}
+
+/// `+H:MM` or `-H:MM`
+fn parse_offset(s: &str) -> Option<SignedDuration> {
+    let (sign, s) = s.split_at(1);
+    let (hours, minutes) = s.split_once(':')?;
+    let (hours, minutes): (i64, i64) = (hours.parse().ok()?, minutes.parse().ok()?);
+    if !(0..60).contains(&minutes) || hours < 0 {
+        return None;
+    }
+    let offset = SignedDuration::from_mins(hours * 60 + minutes);
+    Some(if sign == "-" { -offset } else { offset })
+}
+
+impl fmt::Display for Anchor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (base, offset) = match self {
+            Anchor::Sleep(offset) => ("sleep", offset),
+            Anchor::Wake(offset) => ("wake", offset),
+            Anchor::At(time) => return write!(f, "{}", time.strftime("%H:%M")),
+        };
+        let mins = offset.as_mins();
+        match mins {
+            0 => f.write_str(base),
+            _ => {
+                let sign = if mins < 0 { '-' } else { '+' };
+                let mins = mins.abs();
+                write!(f, "{base}{sign}{}:{:02}", mins / 60, mins % 60)
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for Anchor {
+    type Error = SettingsError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Anchor> for String {
+    fn from(anchor: Anchor) -> Self {
+        anchor.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jiff::{civil::time, SignedDuration};
+
+    use crate::{settings::SettingsError, temp::Temp};
+
+    use super::{validate, Anchor, ProfilePoint};
+
+    #[test]
+    fn test_anchors() {
+        let profile: Vec<ProfilePoint> = serde_json::from_str(
+            r#"[
+                -10,
+                { "at": "sleep+0:30", "temp": { "c": 21 } },
+                { "at": "03:00", "temp": 0 },
+                { "f": 80 },
+                { "at": "wake-1:15", "temp": 20 }
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(profile[0], ProfilePoint::Even(Temp::Level(-10)));
+        assert_eq!(profile[3], ProfilePoint::Even(Temp::Fahrenheit { f: 80. }));
+        let anchors: Vec<_> = profile.iter().filter_map(ProfilePoint::anchor).collect();
+        assert_eq!(
+            anchors,
+            vec![
+                &Anchor::Sleep(SignedDuration::from_mins(30)),
+                &Anchor::At(time(3, 0, 0, 0)),
+                &Anchor::Wake(SignedDuration::from_mins(-75)),
+            ]
+        );
+        // written back the way it was given
+        assert_eq!(
+            serde_json::to_string(&profile[4]).unwrap(),
+            r#"{"at":"wake-1:15","temp":20}"#
+        );
+
+        let (sleep, wake) = (time(22, 0, 0, 0), time(7, 0, 0, 0));
+        assert_eq!(anchors[1].offset(sleep, wake), 5 * 3600);
+        assert_eq!(anchors[2].offset(sleep, wake), 7 * 3600 + 45 * 60);
+        assert!(validate(&profile, sleep, wake).is_ok());
+
+        // 03:00 is before a 04:00 bedtime
+        assert!(matches!(
+            validate(&profile, time(4, 0, 0, 0), time(12, 0, 0, 0)),
+            Err(SettingsError::InvalidProfilePoint(
+                Anchor::At(_),
+                "outside of the sleep period"
+            ))
+        ));
+        let swapped = [profile[2], profile[1]];
+        assert!(matches!(
+            validate(&swapped, sleep, wake),
+            Err(SettingsError::InvalidProfilePoint(
+                Anchor::Sleep(_),
+                "out of order"
+            ))
+        ));
+
+        for bad in [
+            r#""sleep+30""#,
+            r#""wake-1:75""#,
+            r#""noon""#,
+            r#""sleep*1:00""#,
+        ] {
+            assert!(serde_json::from_str::<Anchor>(bad).is_err());
+        }
+    }
+}
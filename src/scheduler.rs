@@ -1,24 +1,43 @@
-use std::time::Duration;
+use std::{
+    f64::consts::PI,
+    ops::Range,
+    time::{Duration, SystemTime},
+};
 
-use jiff::{civil::Time, tz::TimeZone, SignedDuration, Timestamp, ToSpan, Unit, Zoned};
-use log::{error, info};
+use jiff::{
+    civil::{Date, Time},
+    tz::TimeZone,
+    SignedDuration, Timestamp, Unit, Zoned,
+};
+use log::{error, info, warn};
 use thiserror::Error;
 use tokio::{
-    sync::{
-        mpsc,
-        watch::{error::RecvError, Receiver, Ref},
-    },
-    time::sleep,
+    sync::watch::{error::RecvError, Receiver, Sender},
+    task::AbortHandle,
+    time::{interval, sleep},
 };
 
 use crate::{
+    calendar,
     frank::{
         command::{FrankCommand, SideTarget},
-        state::FrankSettings,
+        handle::FrankHandle,
     },
-    settings::{BySideSettings, Settings, SideSettings},
+    settings::{Gain, Ramp, RampCurve, Settings, SideSettings},
+    SETTINGS_FILE,
 };
 
+/// How long a scheduled command may wait in line for Frank
+/// before it is too stale to be worth sending
+const CMD_TIMEOUT: Duration = Duration::from_secs(1800);
+/// Most temperature updates a ramp sends Frank in a night,
+/// its resolution is lowered to stay within this
+const MAX_RAMP_STEPS: i64 = 120;
+/// How long before a vibration alarm goes off Frank is told about it
+const ALARM_LEAD: SignedDuration = SignedDuration::from_mins(7);
+/// How often to look for schedule exceptions and naps that are over
+const EXPIRE_INT: Duration = Duration::from_secs(3600);
+
 #[derive(Error, Debug)]
 pub enum SchedulerError {
     #[error("jiff error: `{0}`")]
@@ -30,195 +49,710 @@ pub enum SchedulerError {
 /// This function tries to never crash, unless there is Jiff error, in which case we want to crash
 /// (either its a core issue that needs to be fixed or a configuration issue)
 pub async fn run(
-    frank_tx: mpsc::Sender<FrankCommand>,
+    frank: FrankHandle,
     mut cfg_rx: Receiver<Settings>,
 ) -> Result<(), SchedulerError> {
+    let mut prev_overrides = Vec::new();
     loop {
-        let handle = {
-            let cfg = cfg_rx.borrow_and_update();
+        let cfg = cfg_rx.borrow_and_update().clone();
+        // noted before reading it, so no change is missed
+        let calendar = match &cfg.calendar {
+            Some(c) => Some((c.file.clone(), calendar::modified(&c.file).await)),
+            None => None,
+        };
+        let cfg = with_calendar(cfg).await;
+
+        let handles = {
+            let mut handles = Vec::new();
 
             // set settings
-            if let Some(bri) = cfg.led_brightness {
-                let res = frank_tx
-                    .send(FrankCommand::SetSettings(Box::new(FrankSettings {
-                        version: 1,
-                        gain_right: 400,
-                        gain_left: 400,
-                        led_brightness_perc: bri,
-                    })))
-                    .await;
-                if let Err(e) = res {
-                    error!("[Scheduler] Frank channel error {e}");
+            if cfg.led_brightness.is_some() || !cfg.gain.is_unset() {
+                let task = set_settings(frank.clone(), cfg.led_brightness, cfg.gain);
+                handles.push(tokio::spawn(task).abort_handle());
+            }
+
+            // temperatures set by hand and naps, which pause the profile while they last
+            let now = Timestamp::now();
+            let mut overrides = Vec::new();
+            for (side, side_cfg) in cfg.sides() {
+                if let Some(nap) = side_cfg.active_nap(now) {
+                    overrides.push((side.clone(), nap.start..nap.end()));
+                    let task = take_nap(
+                        frank.clone(),
+                        side.clone(),
+                        side_cfg.clone(),
+                        cfg.timezone.clone(),
+                    );
+                    handles.push(tokio::spawn(task).abort_handle());
+                }
+                if let Some(temp_override) = side_cfg.active_override(now) {
+                    overrides.push((side.clone(), now..temp_override.until));
+                    let tz = cfg.timezone.clone();
+                    let task = hold_override(frank.clone(), side, side_cfg.clone(), tz);
+                    handles.push(tokio::spawn(task).abort_handle());
+                } else if is_overridden(&prev_overrides, &side, now)
+                    && !is_overridden(&overrides, &side, now)
+                {
+                    // ended early, so don't leave the side where it was set
+                    let now = now.to_zoned(cfg.timezone.clone());
+                    if let Some(cmd) = current_step(side_cfg, side, &now)? {
+                        submit(&frank, cmd);
+                    }
                 }
             }
+            prev_overrides = overrides.clone();
 
-            // make schedule and run it
+            // make each side's schedule and run it
             if !cfg.away_mode {
                 let tz = cfg.timezone.clone();
-                let mut schedule = make_schedule(cfg)?;
-                schedule.sort_by_key(|(z, _)| z.clone());
-
-                info!(
-                    "[Scheduler] New schedule has {} events: {:#?}",
-                    schedule.len(),
-                    schedule
-                );
-
-                Some(tokio::spawn(task(frank_tx.clone(), schedule, tz)).abort_handle())
-            } else {
-                None
+                let now = Timestamp::now().to_zoned(tz.clone());
+
+                // whatever would be going on right now, in case we're starting mid-night
+                for cmd in catch_up(&cfg, &now, &overrides)? {
+                    submit(&frank, cmd);
+                }
+
+                if let Some(prime_time) = cfg.prime {
+                    let task = prime(frank.clone(), prime_time, tz.clone());
+                    handles.push(tokio::spawn(task).abort_handle());
+                }
+
+                for (side, side_cfg) in cfg.sides() {
+                    let task = task(
+                        frank.clone(),
+                        side,
+                        side_cfg.clone(),
+                        tz.clone(),
+                        overrides.clone(),
+                    );
+                    handles.push(tokio::spawn(task).abort_handle());
+                }
             }
+
+            handles
         };
 
         // wait until next change
-        cfg_rx.changed().await?;
-        handle.map(|h| h.abort());
-        info!("[Scheduler] Settings have changed! Restarting...");
+        tokio::select! {
+            res = cfg_rx.changed() => {
+                res?;
+                info!("[Scheduler] Settings have changed! Restarting...");
+            }
+            _ = calendar_changed(&calendar) => {
+                info!("[Scheduler] Calendar has changed! Restarting...");
+            }
+        }
+        handles.iter().for_each(AbortHandle::abort);
+    }
+}
+
+/// The settings with the exceptions from the calendar, if there is
+/// one, ahead of each side's own exceptions so that those win
+async fn with_calendar(mut cfg: Settings) -> Settings {
+    let Some(calendar) = cfg.calendar.clone() else {
+        return cfg;
+    };
+
+    let today = Timestamp::now().to_zoned(cfg.timezone.clone()).date();
+    let exceptions: Vec<_> = match calendar::load(&calendar, &cfg.timezone).await {
+        Ok(res) => res
+            .into_iter()
+            .map(|res| res.exception)
+            .filter(|e| !e.expired(today))
+            .collect(),
+        Err(e) => {
+            error!("[Scheduler] Failed to read calendar {}: {e}", calendar.file);
+            return cfg;
+        }
+    };
+
+    info!(
+        "[Scheduler] Calendar has {} upcoming exceptions",
+        exceptions.len()
+    );
+    for (_, side) in cfg.sides_mut() {
+        side.exceptions.splice(0..0, exceptions.iter().cloned());
     }
+    cfg
 }
 
-/// run schedule daily
+/// Resolves once the calendar file was modified since it was
+/// last read, or never without one
+async fn calendar_changed(calendar: &Option<(String, Option<SystemTime>)>) {
+    match calendar {
+        Some((file, since)) => calendar::changed(file, *since).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Runs a side's schedule night after night, skipping its
+/// profile steps while it is overridden (until when)
 pub async fn task(
-    frank_tx: mpsc::Sender<FrankCommand>,
-    mut schedule: Vec<(Zoned, FrankCommand)>,
+    frank: FrankHandle,
+    tar: SideTarget,
+    cfg: SideSettings,
     tz: TimeZone,
+    overrides: Vec<(SideTarget, Range<Timestamp>)>,
 ) -> Result<(), SchedulerError> {
+    let mut from = Timestamp::now().to_zoned(tz.clone());
     loop {
-        for (next, cmd) in &mut schedule {
+        let (schedule, wake_dt) = make_schedule(&cfg, tar.clone(), &from, &overrides)?;
+        info!(
+            "[Scheduler] New schedule for {tar:?} has {} events: {:#?}",
+            schedule.len(),
+            schedule
+        );
+
+        for (next, cmd) in schedule {
             let now = Timestamp::now().to_zoned(tz.clone());
-            if *next > now {
-                let dur = Duration::from_secs(now.until(&*next)?.total(Unit::Second)? as u64);
-                info!("[Scheduler] Waiting {dur:#?}");
-
-                sleep(dur).await;
-                let res = frank_tx.send(cmd.clone()).await;
-                if let Err(e) = res {
-                    error!("[Scheduler] Frank channel error {e}");
-                }
+            if next > now {
+                wait_until(&now, &next).await?;
+                submit(&frank, cmd);
             }
+        }
 
-            *next = next.checked_add(1.day())?;
+        // the next night is worked out once this one is over
+        from = wake_dt;
+    }
+}
+
+/// Primes every day at `prime_time`
+async fn prime(frank: FrankHandle, prime_time: Time, tz: TimeZone) -> Result<(), SchedulerError> {
+    let mut from = Timestamp::now().to_zoned(tz.clone());
+    loop {
+        let mut prime_dt = from.with().time(prime_time).build()?;
+        if prime_dt <= from {
+            prime_dt = prime_dt.tomorrow()?;
         }
+
+        wait_until(&Timestamp::now().to_zoned(tz.clone()), &prime_dt).await?;
+        submit(&frank, FrankCommand::Prime);
+        from = prime_dt;
     }
 }
 
-fn make_schedule(cfg: Ref<'_, Settings>) -> Result<Vec<(Zoned, FrankCommand)>, SchedulerError> {
-    let mut res = Vec::new();
+/// Removes schedule exceptions and naps from the settings once they are over
+pub async fn clear_expired(settings_rx: Receiver<Settings>, settings_tx: Sender<Settings>) {
+    let mut interval = interval(EXPIRE_INT);
+    loop {
+        interval.tick().await;
+        let mut settings = settings_rx.borrow().clone();
+        let now = Timestamp::now();
+        let today = now.to_zoned(settings.timezone.clone()).date();
+        let expired = settings.drop_expired(today);
+        let ended = settings.drop_ended_naps(now);
+        if expired {
+            info!("[Scheduler] Removing expired schedule exceptions");
+        }
+        if ended {
+            info!("[Scheduler] Removing ended naps");
+        }
+        if !expired && !ended {
+            continue;
+        }
 
-    let now = Timestamp::now().to_zoned(cfg.timezone.clone());
+        if let Err(e) = settings.save(SETTINGS_FILE) {
+            error!("[Scheduler] Failed to save settings: {e}");
+        }
+        if settings_tx.send(settings).is_err() {
+            return;
+        }
+    }
+}
 
-    info!("[Scheduler] Making schedule at {now}");
+async fn wait_until(now: &Zoned, at: &Zoned) -> Result<(), SchedulerError> {
+    let dur = Duration::from_secs(now.until(at)?.total(Unit::Second)?.max(0.) as u64);
+    info!("[Scheduler] Waiting {dur:#?}");
+    sleep(dur).await;
+    Ok(())
+}
 
-    if let Some(prime_time) = cfg.prime {
-        let mut prime_dt = now.with().time(prime_time).build()?;
-        if now > prime_dt {
-            prime_dt = prime_dt.tomorrow()?;
+/// Holds a side at its override temperature until it ends,
+/// then puts it back on whichever profile step is current
+async fn hold_override(
+    frank: FrankHandle,
+    side: SideTarget,
+    cfg: SideSettings,
+    tz: TimeZone,
+) -> Result<(), SchedulerError> {
+    let Some(temp_override) = &cfg.temp_override else {
+        return Ok(());
+    };
+    let left = temp_override.until.duration_since(Timestamp::now());
+    let secs = left.as_secs().clamp(0, u16::MAX.into()) as u16;
+    info!(
+        "[Scheduler] {side:?} set by hand to {} until {}",
+        temp_override.temp, temp_override.until
+    );
+    let cmd = FrankCommand::SetTemp(side.clone(), temp_override.temp.level(), secs);
+    submit(&frank, cmd);
+
+    sleep(Duration::try_from(left).unwrap_or_default()).await;
+    let until = temp_override.until;
+    if cfg.active_nap(until).is_some_and(|nap| nap.start <= until) {
+        info!("[Scheduler] {side:?} override ended, still napping");
+        return Ok(());
+    }
+    let now = until.to_zoned(tz);
+    match current_step(&cfg, side.clone(), &now)? {
+        Some(cmd) => {
+            info!("[Scheduler] {side:?} override ended, back to the profile");
+            submit(&frank, cmd);
         }
-        res.push((prime_dt, FrankCommand::Prime));
+        None => info!("[Scheduler] {side:?} override ended outside of the profile"),
     }
+    Ok(())
+}
 
-    match &cfg.by_side {
-        BySideSettings::Couples { left, right } => {
-            schedule_side(&mut res, left, SideTarget::Left, &now, &cfg.timezone)?;
-            schedule_side(&mut res, right, SideTarget::Right, &now, &cfg.timezone)?;
+/// Holds a side at its nap temperature while the nap lasts, wakes it with the
+/// nap's alarm if it has one, then puts it back on whichever profile step is current
+async fn take_nap(
+    frank: FrankHandle,
+    side: SideTarget,
+    cfg: SideSettings,
+    tz: TimeZone,
+) -> Result<(), SchedulerError> {
+    let Some(nap) = &cfg.nap else {
+        return Ok(());
+    };
+    let (start, end) = (
+        nap.start.to_zoned(tz.clone()),
+        nap.end().to_zoned(tz.clone()),
+    );
+    wait_until(&Timestamp::now().to_zoned(tz.clone()), &start).await?;
+
+    let left = end.timestamp().duration_since(Timestamp::now());
+    let secs = left.as_secs().clamp(0, u16::MAX.into()) as u16;
+    info!("[Scheduler] {side:?} napping at {} until {end}", nap.temp);
+    submit(
+        &frank,
+        FrankCommand::SetTemp(side.clone(), nap.temp.level(), secs),
+    );
+    if let Some(vib) = &nap.vibration {
+        let vib_dt = end.checked_sub(SignedDuration::from_secs(vib.offset.into()))?;
+        if vib_dt > Timestamp::now().to_zoned(tz.clone()) {
+            let vib_settings = Box::new((vib.clone(), vib_dt));
+            submit(&frank, FrankCommand::SetAlarm(side.clone(), vib_settings));
         }
-        BySideSettings::Solo { both } => {
-            schedule_side(&mut res, both, SideTarget::Both, &now, &cfg.timezone)?;
+    }
+
+    wait_until(&Timestamp::now().to_zoned(tz), &end).await?;
+    if cfg.active_override(end.timestamp()).is_some() {
+        info!("[Scheduler] {side:?} nap ended, still set by hand");
+        return Ok(());
+    }
+    match current_step(&cfg, side.clone(), &end)? {
+        Some(cmd) => {
+            info!("[Scheduler] {side:?} nap ended, back to the profile");
+            submit(&frank, cmd);
         }
+        None => info!("[Scheduler] {side:?} nap ended outside of the profile"),
     }
+    Ok(())
+}
 
-    Ok(res)
+/// Whether `side` is set by hand or napping `at` then
+fn is_overridden(
+    overrides: &[(SideTarget, Range<Timestamp>)],
+    side: &SideTarget,
+    at: Timestamp,
+) -> bool {
+    overrides
+        .iter()
+        .any(|(o_side, during)| o_side == side && during.contains(&at))
 }
 
-fn schedule_side(
-    res: &mut Vec<(Zoned, FrankCommand)>,
+/// Sends Frank the configured settings, keeping his
+/// current values for anything that isn't configured
+async fn set_settings(frank: FrankHandle, led_brightness: Option<u8>, gain: Gain) {
+    let cur = match frank.refresh(CMD_TIMEOUT).await {
+        Ok(state) => state.settings,
+        Err(e) => {
+            error!("[Scheduler] Failed to read Frank's settings: {e}");
+            return;
+        }
+    };
+
+    let mut new = cur.clone();
+    if let Some(bri) = led_brightness {
+        new.led_brightness_perc = bri;
+    }
+    if let Some(gain) = gain.left {
+        new.gain_left = gain;
+    }
+    if let Some(gain) = gain.right {
+        new.gain_right = gain;
+    }
+
+    if new != cur {
+        submit(&frank, FrankCommand::SetSettings(Box::new(new)));
+    }
+}
+
+/// Queues a command for Frank, logging the result once he gets to it
+fn submit(frank: &FrankHandle, cmd: FrankCommand) {
+    match frank.submit(cmd.clone(), CMD_TIMEOUT) {
+        Ok(pending) => {
+            tokio::spawn(async move {
+                match pending.wait().await {
+                    Ok(()) => info!("[Scheduler] Frank ran {cmd:?}"),
+                    Err(e) => error!("[Scheduler] Frank failed to run {cmd:?}: {e}"),
+                }
+            });
+        }
+        Err(e) => error!("[Scheduler] Frank channel error {e}"),
+    }
+}
+
+/// The events of `cfg`'s night going on at `now` (or the next
+/// one), and when it wakes up, after which the next night starts.
+/// Profile steps while the side is overridden are left out.
+fn make_schedule(
     cfg: &SideSettings,
     tar: SideTarget,
     now: &Zoned,
-    tz: &TimeZone,
-) -> Result<(), SchedulerError> {
-    let (sleep_dt, mut wake_dt) = calc_sleep_wake_dts(now, cfg.sleep, cfg.wake)?;
+    overrides: &[(SideTarget, Range<Timestamp>)],
+) -> Result<(Vec<(Zoned, FrankCommand)>, Zoned), SchedulerError> {
+    let mut res = Vec::new();
+    let Night {
+        cfg,
+        sleep_dt,
+        wake_dt,
+        skip,
+    } = calc_night(cfg, now)?;
+
+    info!("[Scheduler] Making {tar:?} schedule at {now}");
+    if skip {
+        info!("[Scheduler] Skipping {tar:?} night waking at {wake_dt}");
+        return Ok((res, wake_dt));
+    }
 
     if let Some(vib) = &cfg.vibration {
         let vib_dt = wake_dt.checked_sub(SignedDuration::from_secs(vib.offset.into()))?;
-        let vib_settings = Box::new((vib.clone(), vib_dt.time(), tz.clone()));
-        // let Frank know about the alarm ahead of time
-        let set_vib_dt = vib_dt.checked_sub(SignedDuration::from_mins(7))?;
-        res.push((set_vib_dt, FrankCommand::SetAlarm(tar.clone(), vib_settings)));
+        let set_vib_dt = vib_dt.checked_sub(ALARM_LEAD)?;
+        let vib_settings = Box::new((vib.clone(), vib_dt));
+        res.push((
+            set_vib_dt,
+            FrankCommand::SetAlarm(tar.clone(), vib_settings),
+        ));
     }
 
     if let Some(heat) = &cfg.heat {
-        let heat_start_dt = wake_dt.clone();
-        wake_dt = wake_dt.checked_sub(SignedDuration::from_secs(heat.offset.into()))?;
         res.push((
-            heat_start_dt,
-            FrankCommand::SetTemp(tar.clone(), heat.temp, heat.offset),
+            wake_dt.clone(),
+            FrankCommand::SetTemp(tar.clone(), heat.temp.level(), heat.offset),
         ));
     }
+    let end = profile_end(&cfg, &wake_dt)?;
 
-    info!("[Scheduler] Result for {tar:?}: sleep at {sleep_dt}, wake at {wake_dt}");
+    info!("[Scheduler] Result for {tar:?}: sleep at {sleep_dt}, wake at {end}");
 
-    calc_profile(res, tar, &cfg.temp_profile, sleep_dt, wake_dt)?;
+    let mut steps = Vec::new();
+    calc_steps(&mut steps, tar.clone(), &cfg, &sleep_dt, &wake_dt)?;
+    let (skipped, steps): (Vec<_>, _) = steps
+        .into_iter()
+        .partition(|(at, _)| is_overridden(overrides, &tar, at.timestamp()));
+    if !skipped.is_empty() {
+        info!(
+            "[Scheduler] Skipping {} {tar:?} steps, the side was set by hand",
+            skipped.len()
+        );
+    }
+    res.extend(steps);
+    res.sort_by_key(|(z, _)| z.clone());
 
-    Ok(())
+    Ok((res, wake_dt))
 }
 
-fn calc_sleep_wake_dts(
+/// The temperature and alarm each side should have `now`, for events in
+/// the schedule that have already passed. Overridden sides keep their
+/// temperature until the heat alarm.
+fn catch_up(
+    cfg: &Settings,
     now: &Zoned,
-    sleep_time: Time,
-    wake_time: Time,
-) -> Result<(Zoned, Zoned), SchedulerError> {
-    let mut sleep = now.with().time(sleep_time).build()?;
-    let mut wake = now.with().time(wake_time).build()?;
-
-    // idk why this messes with my head so much
-
-    // 20:00 -> 7:00
-    if sleep > wake {
-        // @ 3:00 -> 20:00 yesterday, 7:00 today
-        if now < wake {
-            sleep = sleep.yesterday()?;
+    overrides: &[(SideTarget, Range<Timestamp>)],
+) -> Result<Vec<FrankCommand>, SchedulerError> {
+    let mut res = Vec::new();
+    for (tar, side_cfg) in cfg.sides() {
+        let night = calc_night(side_cfg, now)?;
+        let wake_dt = &night.wake_dt;
+
+        // the alarm is set ALARM_LEAD before it goes off
+        if let Some(vib) = night.cfg.vibration.as_ref().filter(|_| !night.skip) {
+            let vib_dt = wake_dt.checked_sub(SignedDuration::from_secs(vib.offset.into()))?;
+            let set_vib_dt = vib_dt.checked_sub(ALARM_LEAD)?;
+            if set_vib_dt <= *now && *now < vib_dt {
+                info!("[Scheduler] Catching up on {tar:?} alarm at {vib_dt}");
+                res.push(FrankCommand::SetAlarm(
+                    tar.clone(),
+                    Box::new((vib.clone(), vib_dt)),
+                ));
+            }
         }
-        // @ 10:00 = 20:00 today, 7:00 tomorrow
-        // @ 19:00 = 20:00 today, 7:00 tomorrow
-        // @ 21:00 = 20:00 today, 7:00 tomorrow
-        else {
-            wake = wake.tomorrow()?;
+
+        if let Some(cmd) = current_step(side_cfg, tar.clone(), now)?
+            && !is_overridden(overrides, &tar, now.timestamp())
+        {
+            info!("[Scheduler] Catching up on {cmd:?}");
+            res.push(cmd);
+        }
+
+        // the heat alarm starts at wake, which may have just passed
+        let prev = night_on(side_cfg, wake_dt.date().yesterday()?, now.time_zone())?;
+        for night in [prev, night] {
+            if let Some(heat) = night.cfg.heat.as_ref().filter(|_| !night.skip) {
+                let into = night.wake_dt.duration_until(now).as_secs();
+                if (0..heat.offset.into()).contains(&into) {
+                    let left = heat.offset - into as u16;
+                    info!("[Scheduler] Catching up on {tar:?} heat alarm");
+                    res.push(FrankCommand::SetTemp(tar.clone(), heat.temp.level(), left));
+                }
+            }
         }
     }
-    // # 1:00 -> 9:00
-    //  @ 3:00 = 1:00 today, 9:00 today
-    //  @ 10:00 = 1:00 tomorrow, 9:00 tomorrow
-    //  @ 19:00 = 1:00 tomorrow, 9:00 tomorrow
-    //
-    // # 12:00 -> 16:00
-    //  @ 3:00 = 12:00 today, 16:00 today
-    //  @ 13:00 = 12:00 today, 16:00 today
-    //  @ 17:00 = 12:00 tomorrow, 16:00 tomorrow
-    else if now > wake {
-        sleep = sleep.tomorrow()?;
-        wake = wake.tomorrow()?;
-    }
-
-    Ok((sleep, wake))
+    Ok(res)
 }
 
-fn calc_profile(
-    res: &mut Vec<(Zoned, FrankCommand)>,
+/// When `cfg` next wakes up after `now`
+pub fn next_wake(cfg: &SideSettings, now: &Zoned) -> Result<Zoned, SchedulerError> {
+    Ok(calc_night(cfg, now)?.wake_dt)
+}
+
+/// The profile ends early to leave room for the heat alarm
+fn profile_end(cfg: &SideSettings, wake_dt: &Zoned) -> Result<Zoned, SchedulerError> {
+    match &cfg.heat {
+        Some(heat) => Ok(wake_dt.checked_sub(SignedDuration::from_secs(heat.offset.into()))?),
+        None => Ok(wake_dt.clone()),
+    }
+}
+
+/// The profile step `at` falls in, set for however much of the step is left
+fn current_step(
+    cfg: &SideSettings,
     tar: SideTarget,
-    prof: &Vec<i16>,
+    at: &Zoned,
+) -> Result<Option<FrankCommand>, SchedulerError> {
+    let Night {
+        cfg,
+        sleep_dt,
+        wake_dt,
+        skip,
+    } = calc_night(cfg, at)?;
+    let end = profile_end(&cfg, &wake_dt)?;
+    if skip || *at < sleep_dt || *at >= end {
+        return Ok(None);
+    }
+
+    let mut steps = Vec::new();
+    calc_steps(&mut steps, tar, &cfg, &sleep_dt, &wake_dt)?;
+    let step = steps.into_iter().rev().find(|(start, _)| start <= at);
+    Ok(step.map(|(start, cmd)| match cmd {
+        FrankCommand::SetTemp(side, temp, len) => {
+            let into = start.duration_until(at).as_secs() as u16;
+            FrankCommand::SetTemp(side, temp, len.saturating_sub(into))
+        }
+        cmd => cmd,
+    }))
+}
+
+/// A side's night, with the settings for the day it wakes up on
+struct Night {
+    cfg: SideSettings,
     sleep_dt: Zoned,
     wake_dt: Zoned,
+    /// nothing happens, but the night still ends at `wake_dt`
+    skip: bool,
+}
+
+/// The night going on at `now`, or the next one
+fn calc_night(cfg: &SideSettings, now: &Zoned) -> Result<Night, SchedulerError> {
+    let today = night_on(cfg, now.date(), now.time_zone())?;
+    if today.wake_dt > *now {
+        return Ok(today);
+    }
+    night_on(cfg, now.date().tomorrow()?, now.time_zone())
+}
+
+/// The night that wakes up on `date`
+fn night_on(cfg: &SideSettings, date: Date, tz: &TimeZone) -> Result<Night, SchedulerError> {
+    let skip = cfg.skips(date);
+    let cfg = cfg.on(date);
+    let wake_dt = date.to_datetime(cfg.wake).to_zoned(tz.clone())?;
+    let mut sleep_dt = date.to_datetime(cfg.sleep).to_zoned(tz.clone())?;
+
+    // 20:00 -> 7:00 goes to sleep the day before,
+    // 1:00 -> 9:00 and 12:00 -> 16:00 the same day
+    if sleep_dt >= wake_dt {
+        sleep_dt = sleep_dt.yesterday()?;
+    }
+
+    Ok(Night {
+        cfg,
+        sleep_dt,
+        wake_dt,
+        skip,
+    })
+}
+
+/// The profile as steps, or as a ramp if `cfg` has one
+fn calc_steps(
+    res: &mut Vec<(Zoned, FrankCommand)>,
+    tar: SideTarget,
+    cfg: &SideSettings,
+    sleep_dt: &Zoned,
+    wake_dt: &Zoned,
 ) -> Result<(), SchedulerError> {
-    let sleep_period = sleep_dt.until(&wake_dt)?.total(Unit::Second)? as i64;
-    let step_len = SignedDuration::from_secs(sleep_period / prof.len() as i64);
-    let step_len_secs = step_len.as_secs() as u16;
+    let end = profile_end(cfg, wake_dt)?;
+    let prof = profile_points(cfg, &tar, sleep_dt, wake_dt, &end)?;
+    match &cfg.ramp {
+        Some(ramp) if prof.len() > 1 => calc_ramp(res, tar, &prof, ramp, sleep_dt, &end),
+        _ => calc_profile(res, tar, &prof, sleep_dt, &end),
+    }
+}
 
-    info!("[Scheduler] Result for {tar:?}: sleep period {sleep_period} seconds with each step {step_len_secs} seconds");
+/// The profile's heat levels, with how many seconds after sleep the pinned ones start.
+/// Pinned points that don't land in order before `end` are left out.
+fn profile_points(
+    cfg: &SideSettings,
+    tar: &SideTarget,
+    sleep_dt: &Zoned,
+    wake_dt: &Zoned,
+    end: &Zoned,
+) -> Result<Vec<(Option<i64>, i16)>, SchedulerError> {
+    let sleep_period = sleep_dt.duration_until(end).as_secs();
+    let mut res = Vec::new();
+    let mut prev = -1;
+    for point in &cfg.temp_profile {
+        let at = match point.anchor() {
+            Some(anchor) => {
+                let at = sleep_dt.duration_until(&anchor.resolve(sleep_dt, wake_dt)?);
+                let at = at.as_secs();
+                if at <= prev || at >= sleep_period {
+                    warn!("[Scheduler] Leaving out {tar:?} profile point at {anchor}, outside of the profile");
+                    continue;
+                }
+                prev = at;
+                Some(at)
+            }
+            None => None,
+        };
+        res.push((at, point.temp().level()));
+    }
+    Ok(res)
+}
 
-    for (i, temp) in prof.iter().enumerate() {
-        let dt = sleep_dt.checked_add(step_len * i as i32)?;
-        res.push((dt, FrankCommand::SetTemp(tar.clone(), *temp, step_len_secs)));
+/// When each point starts (seconds after sleep), with the points that aren't
+/// pinned spread evenly between those that are. The first point starts at sleep,
+/// and a ramp's last point is reached at the end of the `sleep_period`.
+fn layout(prof: &[(Option<i64>, i16)], sleep_period: i64, ramp: bool) -> Vec<i64> {
+    let mut starts: Vec<_> = prof.iter().map(|(at, _)| *at).collect();
+    let (Some(first), Some(last)) = (starts.first_mut(), prof.last()) else {
+        return starts.into_iter().flatten().collect();
+    };
+    first.get_or_insert(0);
+    if ramp && last.0.is_none() {
+        *starts.last_mut().unwrap() = Some(sleep_period);
+    }
+    // steps after the last pinned point share the rest of the night
+    starts.push(Some(sleep_period));
+
+    let mut prev = 0;
+    for next in 1..starts.len() {
+        if let (Some(from), Some(to)) = (starts[prev], starts[next]) {
+            let gaps = (next - prev) as i64;
+            for (k, start) in starts[prev + 1..next].iter_mut().enumerate() {
+                *start = Some(from + (to - from) * (k as i64 + 1) / gaps);
+            }
+            prev = next;
+        }
+    }
+    starts.pop();
+    starts.into_iter().flatten().collect()
+}
+
+/// Like [`calc_profile`], but going smoothly from each point to the next
+/// with a temperature update every `ramp.resolution` seconds in between
+fn calc_ramp(
+    res: &mut Vec<(Zoned, FrankCommand)>,
+    tar: SideTarget,
+    prof: &[(Option<i64>, i16)],
+    ramp: &Ramp,
+    sleep_dt: &Zoned,
+    wake_dt: &Zoned,
+) -> Result<(), SchedulerError> {
+    let sleep_period = sleep_dt.duration_until(wake_dt).as_secs();
+    let min_resolution = (sleep_period + MAX_RAMP_STEPS - 1) / MAX_RAMP_STEPS;
+    let resolution = i64::from(ramp.resolution).max(min_resolution);
+    let knots: Vec<_> = layout(prof, sleep_period, true)
+        .into_iter()
+        .zip(prof.iter().map(|(_, temp)| *temp))
+        .collect();
+    let first = knots.first().map_or(0, |(start, _)| *start);
+
+    // updates that wouldn't change anything are left out
+    let mut steps: Vec<(i64, i16)> = Vec::new();
+    for offset in (first..sleep_period).step_by(resolution as usize) {
+        let temp = interpolate(ramp.curve, &knots, offset);
+        if steps.last().is_none_or(|(_, last)| *last != temp) {
+            steps.push((offset, temp));
+        }
+    }
+
+    info!(
+        "[Scheduler] Result for {tar:?}: ramp over {sleep_period} seconds in {} steps",
+        steps.len()
+    );
+
+    for (i, &(offset, temp)) in steps.iter().enumerate() {
+        let next = steps.get(i + 1).map_or(sleep_period, |(next, _)| *next);
+        let dt = sleep_dt.checked_add(SignedDuration::from_secs(offset))?;
+        let len = (next - offset) as u16;
+        res.push((dt, FrankCommand::SetTemp(tar.clone(), temp, len)));
+    }
+
+    Ok(())
+}
+
+/// The temperature `at` seconds into the night, between the `knots` around it
+/// or held after the last one
+fn interpolate(curve: RampCurve, knots: &[(i64, i16)], at: i64) -> i16 {
+    let i = knots
+        .iter()
+        .rposition(|(start, _)| *start <= at)
+        .unwrap_or(0);
+    let (from_at, from) = knots[i];
+    let Some(&(to_at, to)) = knots.get(i + 1) else {
+        return from;
+    };
+    let frac = (at - from_at) as f64 / (to_at - from_at) as f64;
+    let t = match curve {
+        RampCurve::Linear => frac,
+        RampCurve::Cosine => (1. - (frac * PI).cos()) / 2.,
+    };
+    let (from, to) = (f64::from(from), f64::from(to));
+    (from + (to - from) * t).round() as i16
+}
+
+fn calc_profile(
+    res: &mut Vec<(Zoned, FrankCommand)>,
+    tar: SideTarget,
+    prof: &[(Option<i64>, i16)],
+    sleep_dt: &Zoned,
+    wake_dt: &Zoned,
+) -> Result<(), SchedulerError> {
+    let sleep_period = sleep_dt.duration_until(wake_dt).as_secs();
+    let starts = layout(prof, sleep_period, false);
+
+    info!(
+        "[Scheduler] Result for {tar:?}: sleep period {sleep_period} seconds in {} steps",
+        prof.len()
+    );
+
+    for (i, (start, (_, temp))) in starts.iter().zip(prof).enumerate() {
+        let next = starts.get(i + 1).copied().unwrap_or(sleep_period);
+        let dt = sleep_dt.checked_add(SignedDuration::from_secs(*start))?;
+        let len = (next - start) as u16;
+        res.push((dt, FrankCommand::SetTemp(tar.clone(), *temp, len)));
     }
 
     Ok(())
@@ -232,9 +766,16 @@ mod tests {
         Timestamp, Zoned,
     };
 
-    use crate::frank::command::{FrankCommand, SideTarget};
+    use crate::{
+        frank::command::{FrankCommand, SideTarget},
+        settings::{Ramp, RampCurve, Settings, SideSettings},
+        temp::Temp,
+    };
 
-    use super::{calc_profile, calc_sleep_wake_dts};
+    use super::{
+        calc_night, calc_profile, calc_ramp, catch_up, current_step, is_overridden, make_schedule,
+        MAX_RAMP_STEPS,
+    };
 
     fn today_at(hour: i8, minute: i8) -> Zoned {
         Timestamp::now()
@@ -257,6 +798,23 @@ mod tests {
         time(hour, minute, 0, 0)
     }
 
+    fn calc_sleep_wake_dts(now: &Zoned, sleep: Time, wake: Time) -> (Zoned, Zoned) {
+        let cfg = SideSettings {
+            temp_profile: vec![Temp::Level(0).into()],
+            ramp: None,
+            sleep,
+            wake,
+            vibration: None,
+            heat: None,
+            temp_override: None,
+            nap: None,
+            weekly: Default::default(),
+            exceptions: vec![],
+        };
+        let night = calc_night(&cfg, now).unwrap();
+        (night.sleep_dt, night.wake_dt)
+    }
+
     #[test]
     fn test_normal_sleep_schedule() {
         let sleep_time = ctime(22, 0);
@@ -264,13 +822,13 @@ mod tests {
 
         let now = today_at(3, 0);
         assert_eq!(
-            calc_sleep_wake_dts(&now, sleep_time, wake_time).unwrap(),
+            calc_sleep_wake_dts(&now, sleep_time, wake_time),
             (yesterday_at(22, 0), today_at(7, 0))
         );
 
         let now = today_at(10, 0);
         assert_eq!(
-            calc_sleep_wake_dts(&now, sleep_time, wake_time).unwrap(),
+            calc_sleep_wake_dts(&now, sleep_time, wake_time),
             (today_at(22, 0), tomorrow_at(7, 0))
         );
     }
@@ -282,13 +840,13 @@ mod tests {
 
         let now = today_at(3, 0);
         assert_eq!(
-            calc_sleep_wake_dts(&now, sleep_time, wake_time).unwrap(),
+            calc_sleep_wake_dts(&now, sleep_time, wake_time),
             (today_at(1, 0), today_at(9, 0))
         );
 
         let now = today_at(10, 0);
         assert_eq!(
-            calc_sleep_wake_dts(&now, sleep_time, wake_time).unwrap(),
+            calc_sleep_wake_dts(&now, sleep_time, wake_time),
             (tomorrow_at(1, 0), tomorrow_at(9, 0))
         );
     }
@@ -297,12 +855,12 @@ mod tests {
     fn test_profile() {
         let sleep_dt = today_at(23, 0);
         let wake_dt = tomorrow_at(8, 0);
-        let prof = vec![-10, 0, 10];
+        let prof = vec![(None, -10), (None, 0), (None, 10)];
 
         let tar = SideTarget::Both;
         let mut actual = Vec::new();
-        calc_profile(&mut actual, tar.clone(), &prof, sleep_dt, wake_dt).unwrap();
-        let step_len_secs = 3 * 3600 as u16;
+        calc_profile(&mut actual, tar.clone(), &prof, &sleep_dt, &wake_dt).unwrap();
+        let step_len_secs = 3 * 3600_u16;
 
         let expected = vec![
             (
@@ -321,4 +879,436 @@ mod tests {
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_ramp() {
+        let ramp = |curve, resolution| {
+            let mut res = Vec::new();
+            let ramp = Ramp { curve, resolution };
+            let (sleep_dt, wake_dt) = (today_at(22, 0), tomorrow_at(6, 0));
+            calc_ramp(
+                &mut res,
+                SideTarget::Both,
+                &[(None, -20), (None, 20)],
+                &ramp,
+                &sleep_dt,
+                &wake_dt,
+            )
+            .unwrap();
+            res
+        };
+        let temps = |res: &[(Zoned, FrankCommand)]| -> Vec<_> {
+            res.iter()
+                .map(|(_, cmd)| match cmd {
+                    FrankCommand::SetTemp(_, temp, _) => *temp,
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+
+        let linear = ramp(RampCurve::Linear, 3600);
+        assert_eq!(linear[1].0, today_at(23, 0));
+        assert_eq!(
+            linear[7].1,
+            FrankCommand::SetTemp(SideTarget::Both, 15, 3600)
+        );
+        assert_eq!(temps(&linear), vec![-20, -15, -10, -5, 0, 5, 10, 15]);
+        assert_eq!(
+            temps(&ramp(RampCurve::Cosine, 3600)),
+            vec![-20, -18, -14, -8, 0, 8, 14, 18]
+        );
+
+        // every minute is too many, so updates are spread out
+        let fine = ramp(RampCurve::Cosine, 60);
+        assert!(fine.len() as i64 <= MAX_RAMP_STEPS);
+        let total: u32 = fine
+            .iter()
+            .map(|(_, cmd)| match cmd {
+                FrankCommand::SetTemp(_, _, len) => u32::from(*len),
+                _ => unreachable!(),
+            })
+            .sum();
+        assert_eq!(total, 8 * 3600);
+
+        // held after a pinned last point
+        let mut pinned = Vec::new();
+        let (sleep_dt, wake_dt) = (today_at(22, 0), tomorrow_at(6, 0));
+        let ramp = Ramp {
+            curve: RampCurve::Linear,
+            resolution: 3600,
+        };
+        let prof = [(None, -20), (Some(7200), 20)];
+        calc_ramp(
+            &mut pinned,
+            SideTarget::Both,
+            &prof,
+            &ramp,
+            &sleep_dt,
+            &wake_dt,
+        )
+        .unwrap();
+        assert_eq!(temps(&pinned), vec![-20, 0, 20]);
+        assert_eq!(
+            pinned[2],
+            (
+                tomorrow_at(0, 0),
+                FrankCommand::SetTemp(SideTarget::Both, 20, 6 * 3600)
+            )
+        );
+    }
+
+    #[test]
+    fn test_pinned_profile() {
+        let settings = Settings::from_str(
+            r#"{
+                "timezone": "America/New_York",
+                "both": {
+                    "temp_profile": [
+                        -20,
+                        { "at": "sleep+1:00", "temp": -10 },
+                        0,
+                        10,
+                        { "at": "03:00", "temp": 20 },
+                        { "at": "wake-1:00", "temp": 5 }
+                    ],
+                    "sleep": "22:00",
+                    "wake": "07:00",
+                    "heat": { "temp": 50, "offset": 1800 },
+                    "exceptions": [{ "from": "2025-06-11", "wake": "03:30" }]
+                }
+            }"#,
+        )
+        .unwrap();
+        let both = settings.as_solo().unwrap();
+        let steps = |now: &str| -> Vec<_> {
+            let (schedule, _) =
+                make_schedule(both, SideTarget::Both, &now.parse().unwrap(), &[]).unwrap();
+            schedule
+                .into_iter()
+                .filter_map(|(at, cmd)| match cmd {
+                    FrankCommand::SetTemp(_, temp, len) if temp != 50 => {
+                        Some((at.time(), temp, len))
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // the unpinned points share the time between their neighbours
+        assert_eq!(
+            steps("2025-06-09T12:00-04:00[America/New_York]"),
+            vec![
+                (ctime(22, 0), -20, 3600),
+                (ctime(23, 0), -10, 4800),
+                (ctime(0, 20), 0, 4800),
+                (ctime(1, 40), 10, 4800),
+                (ctime(3, 0), 20, 3 * 3600),
+                (ctime(6, 0), 5, 1800),
+            ]
+        );
+        // 03:00 is when the profile ends for the heat alarm, so it's left out
+        assert_eq!(
+            steps("2025-06-10T12:00-04:00[America/New_York]"),
+            vec![
+                (ctime(22, 0), -20, 3600),
+                (ctime(23, 0), -10, 4200),
+                (ctime(0, 10), 0, 4200),
+                (ctime(1, 20), 10, 4200),
+                (ctime(2, 30), 5, 1800),
+            ]
+        );
+    }
+
+    /// When the alarm is set and when it goes off, in a schedule made at
+    /// `now` for 22:00 -> 07:00 with a vibration alarm 5 minutes before wake,
+    /// and when the next schedule is made from
+    fn scheduled_alarm(now: &str) -> (Zoned, FrankCommand, Zoned) {
+        let settings = Settings::from_str(
+            r#"{
+                "timezone": "America/New_York",
+                "both": {
+                    "temp_profile": [0],
+                    "sleep": "22:00",
+                    "wake": "07:00",
+                    "vibration": { "pattern": "rise", "intensity": 50, "duration": 60, "offset": 300 }
+                }
+            }"#,
+        )
+        .unwrap();
+        let both = settings.as_solo().unwrap();
+        let (schedule, wake_dt) =
+            make_schedule(both, SideTarget::Both, &now.parse().unwrap(), &[]).unwrap();
+        let (set_at, cmd) = schedule
+            .into_iter()
+            .find(|(_, cmd)| matches!(cmd, FrankCommand::SetAlarm(..)))
+            .unwrap();
+        (set_at, cmd, wake_dt)
+    }
+
+    fn alarm_at(cmd: &FrankCommand) -> String {
+        match cmd {
+            FrankCommand::SetAlarm(_, alarm) => alarm.1.to_string(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_alarm_across_midnight() {
+        // set the evening before, the alarm is for the next morning
+        let (set_at, cmd, _) = scheduled_alarm("2025-06-01T21:00[America/New_York]");
+        assert_eq!(
+            set_at.to_string(),
+            "2025-06-02T06:48:00-04:00[America/New_York]"
+        );
+        assert_eq!(
+            alarm_at(&cmd),
+            "2025-06-02T06:55:00-04:00[America/New_York]"
+        );
+
+        // after midnight it is still this morning's alarm
+        let (_, cmd, _) = scheduled_alarm("2025-06-02T01:00[America/New_York]");
+        assert_eq!(
+            alarm_at(&cmd),
+            "2025-06-02T06:55:00-04:00[America/New_York]"
+        );
+    }
+
+    #[test]
+    fn test_alarm_across_dst() {
+        // clocks spring forward at 02:00 on 2025-03-09
+        let (set_at, cmd, _) = scheduled_alarm("2025-03-08T23:00[America/New_York]");
+        assert_eq!(
+            alarm_at(&cmd),
+            "2025-03-09T06:55:00-04:00[America/New_York]"
+        );
+
+        // the next night, made once the night before wakes up, keeps its wall clock time
+        let (_, prev_cmd, prev_wake) = scheduled_alarm("2025-03-08T01:00[America/New_York]");
+        assert_eq!(
+            alarm_at(&prev_cmd),
+            "2025-03-08T06:55:00-05:00[America/New_York]"
+        );
+        let (next_set_at, next_cmd, _) = scheduled_alarm(&prev_wake.to_string());
+        assert_eq!((next_set_at, next_cmd), (set_at, cmd));
+
+        // and fall back at 02:00 on 2025-11-02
+        let (_, cmd, _) = scheduled_alarm("2025-11-01T23:00[America/New_York]");
+        assert_eq!(
+            alarm_at(&cmd),
+            "2025-11-02T06:55:00-05:00[America/New_York]"
+        );
+    }
+
+    #[test]
+    fn test_override_resume() {
+        let settings = Settings::from_str(
+            r#"{
+                "timezone": "UTC",
+                "both": { "temp_profile": [-10, 0, 10], "sleep": "23:00", "wake": "08:00" }
+            }"#,
+        )
+        .unwrap();
+        let both = settings.as_solo().unwrap();
+        let step = |at: &str| current_step(both, SideTarget::Both, &at.parse().unwrap()).unwrap();
+
+        // half way through the second 3 hour step
+        assert_eq!(
+            step("2025-06-02T03:30[UTC]"),
+            Some(FrankCommand::SetTemp(SideTarget::Both, 0, 5400))
+        );
+        assert_eq!(step("2025-06-01T22:30[UTC]"), None);
+        assert_eq!(step("2025-06-02T08:30[UTC]"), None);
+
+        let until: Timestamp = "2025-06-02T03:30:00Z".parse().unwrap();
+        let before: Timestamp = "2025-06-02T02:00:00Z".parse().unwrap();
+        let overrides = vec![(SideTarget::Left, before..until)];
+        assert!(is_overridden(&overrides, &SideTarget::Left, before));
+        assert!(!is_overridden(&overrides, &SideTarget::Right, before));
+        assert!(!is_overridden(&overrides, &SideTarget::Left, until));
+        // a nap that hasn't started yet
+        let earlier: Timestamp = "2025-06-02T01:00:00Z".parse().unwrap();
+        assert!(!is_overridden(&overrides, &SideTarget::Left, earlier));
+    }
+
+    #[test]
+    fn test_catch_up() {
+        let settings = Settings::from_str(
+            r#"{
+                "timezone": "UTC",
+                "both": {
+                    "temp_profile": [-10, 0, 10],
+                    "sleep": "23:00",
+                    "wake": "08:00",
+                    "vibration": { "pattern": "rise", "intensity": 50, "duration": 60, "offset": 300 },
+                    "heat": { "temp": 50, "offset": 1800 }
+                }
+            }"#,
+        )
+        .unwrap();
+        let catch_up = |at: &str| catch_up(&settings, &at.parse().unwrap(), &[]).unwrap();
+        let both = SideTarget::Both;
+
+        // the profile ends at 07:30 for the heat alarm, in 2h50m steps
+        assert_eq!(
+            catch_up("2025-06-02T03:30[UTC]"),
+            vec![FrankCommand::SetTemp(both.clone(), 0, 4200)]
+        );
+
+        // the alarm should already be set at 07:48
+        let vibration = settings.as_solo().unwrap().vibration.clone().unwrap();
+        assert_eq!(
+            catch_up("2025-06-02T07:50[UTC]"),
+            vec![FrankCommand::SetAlarm(
+                both.clone(),
+                Box::new((vibration, "2025-06-02T07:55[UTC]".parse().unwrap()))
+            )]
+        );
+
+        assert_eq!(
+            catch_up("2025-06-02T08:10[UTC]"),
+            vec![FrankCommand::SetTemp(both, 50, 1200)]
+        );
+        assert_eq!(catch_up("2025-06-02T12:00[UTC]"), vec![]);
+    }
+
+    #[test]
+    fn test_override_keeps_alarms() {
+        let settings = Settings::from_str(
+            r#"{
+                "timezone": "UTC",
+                "both": {
+                    "temp_profile": [-10, 0, 10],
+                    "sleep": "23:00",
+                    "wake": "08:00",
+                    "vibration": { "pattern": "rise", "intensity": 50, "duration": 60, "offset": 300 },
+                    "heat": { "temp": 50, "offset": 1800 }
+                }
+            }"#,
+        )
+        .unwrap();
+        let both = settings.as_solo().unwrap();
+        // set by hand from 03:00 until after wake
+        let from: Timestamp = "2025-06-02T03:00:00Z".parse().unwrap();
+        let until: Timestamp = "2025-06-02T09:00:00Z".parse().unwrap();
+        let overrides = [(SideTarget::Both, from..until)];
+
+        // the 04:40 step is skipped, but both alarms still go off
+        let now = "2025-06-01T12:00[UTC]".parse().unwrap();
+        let (schedule, _) = make_schedule(both, SideTarget::Both, &now, &overrides).unwrap();
+        let times: Vec<_> = schedule.iter().map(|(at, _)| at.time()).collect();
+        assert_eq!(
+            times,
+            vec![ctime(23, 0), ctime(1, 50), ctime(7, 48), ctime(8, 0)]
+        );
+        assert_eq!(
+            schedule[3].1,
+            FrankCommand::SetTemp(SideTarget::Both, 50, 1800)
+        );
+
+        let catch_up = |at: &str| catch_up(&settings, &at.parse().unwrap(), &overrides).unwrap();
+        assert_eq!(catch_up("2025-06-02T03:30[UTC]"), vec![]);
+        assert_eq!(
+            catch_up("2025-06-02T08:10[UTC]"),
+            vec![FrankCommand::SetTemp(SideTarget::Both, 50, 1200)]
+        );
+    }
+
+    #[test]
+    fn test_weekly_schedule() {
+        let settings = Settings::from_str(
+            r#"{
+                "timezone": "America/New_York",
+                "both": {
+                    "temp_profile": [0],
+                    "sleep": "22:00",
+                    "wake": "07:00",
+                    "vibration": { "pattern": "rise", "intensity": 50, "duration": 60, "offset": 300 },
+                    "weekly": {
+                        "saturday": { "sleep": "00:30", "wake": "10:00" },
+                        "sunday": { "temp_profile": [-20, 20], "sleep": "23:00", "wake": "09:00" }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        let both = settings.as_solo().unwrap();
+
+        // each night is made once the one before it wakes up, starting on a Friday morning
+        let mut from: Zoned = "2025-06-06T08:00[America/New_York]".parse().unwrap();
+        let mut nights = Vec::new();
+        for _ in 0..3 {
+            let (schedule, wake_dt) = make_schedule(both, SideTarget::Both, &from, &[]).unwrap();
+            let times: Vec<_> = schedule.into_iter().map(|(at, _)| at.to_string()).collect();
+            nights.push(times);
+            from = wake_dt;
+        }
+
+        assert_eq!(
+            nights,
+            vec![
+                // Friday night out, no alarm on Saturday
+                vec!["2025-06-07T00:30:00-04:00[America/New_York]"],
+                // Sunday has its own profile
+                vec![
+                    "2025-06-07T23:00:00-04:00[America/New_York]",
+                    "2025-06-08T04:00:00-04:00[America/New_York]",
+                ],
+                // back to work on Monday
+                vec![
+                    "2025-06-08T22:00:00-04:00[America/New_York]",
+                    "2025-06-09T06:48:00-04:00[America/New_York]",
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_exceptions_schedule() {
+        let settings = Settings::from_str(
+            r#"{
+                "timezone": "UTC",
+                "both": {
+                    "temp_profile": [0],
+                    "sleep": "22:00",
+                    "wake": "07:00",
+                    "vibration": { "pattern": "rise", "intensity": 50, "duration": 60, "offset": 300 },
+                    "exceptions": [
+                        { "from": "2025-06-03", "skip": true },
+                        { "from": "2025-06-05", "wake": "05:30" }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+        let both = settings.as_solo().unwrap();
+
+        let mut from: Zoned = "2025-06-02T08:00[UTC]".parse().unwrap();
+        let mut nights = Vec::new();
+        for _ in 0..3 {
+            let (schedule, wake_dt) = make_schedule(both, SideTarget::Both, &from, &[]).unwrap();
+            let times: Vec<_> = schedule.into_iter().map(|(at, _)| at.to_string()).collect();
+            nights.push(times);
+            from = wake_dt;
+        }
+        assert_eq!(
+            nights,
+            vec![
+                vec![],
+                vec![
+                    "2025-06-03T22:00:00+00:00[UTC]",
+                    "2025-06-04T06:48:00+00:00[UTC]"
+                ],
+                vec![
+                    "2025-06-04T22:00:00+00:00[UTC]",
+                    "2025-06-05T05:18:00+00:00[UTC]"
+                ],
+            ]
+        );
+
+        // nothing to catch up on during a skipped night
+        assert_eq!(
+            catch_up(&settings, &"2025-06-03T03:00[UTC]".parse().unwrap(), &[]).unwrap(),
+            vec![]
+        );
+    }
 }
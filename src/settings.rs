@@ -1,10 +1,24 @@
 use actix_web::{body::BoxBody, HttpRequest, HttpResponse, Responder, ResponseError};
-use jiff::{civil::Time, tz::TimeZone};
+use jiff::{
+    civil::{Date, Time},
+    tz::TimeZone,
+    SignedDuration, Timestamp,
+};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use serde_json;
-use std::{fs, io, num::ParseIntError, str::FromStr};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs, io,
+    num::ParseIntError,
+    str::FromStr,
+};
 use thiserror::Error;
 
+use crate::{
+    frank::command::SideTarget,
+    profile::{self, Anchor, ProfilePoint},
+    temp::Temp,
+};
+
 #[derive(Error, Debug)]
 pub enum SettingsError {
     #[error("file io: `{0}`")]
@@ -15,6 +29,10 @@ pub enum SettingsError {
     ParseInt(#[from] ParseIntError),
     #[error(r#"invalid vibration pattern: `{0}`, espected "double" or "rise""#)]
     InvalidVibrationPattern(String),
+    #[error("invalid gain: `{0}`, expected more than 0")]
+    InvalidGain(u16),
+    #[error("invalid temperature: `{0}`, expected -100 to 100, about 15-39°C, or about 59-102°F")]
+    InvalidTemp(Temp),
     #[error(
         "the settings are currently in Couples mode, use `/left` or `/right` prefixes not `/both`"
     )]
@@ -23,9 +41,31 @@ pub enum SettingsError {
         "the settings are currently in Solo mode, use `/both` prefix not `/left` or `/right`"
     )]
     NotSolo,
+    #[error("there is no vibration alarm set for this side")]
+    NoVibration,
+    #[error("invalid override: {0}")]
+    InvalidOverride(&'static str),
+    #[error("invalid nap: {0}")]
+    InvalidNap(&'static str),
+    #[error("invalid exception: {0}")]
+    InvalidException(&'static str),
+    #[error("there is no exception `{0}` for this side")]
+    NoException(u32),
+    #[error("there is no calendar set")]
+    NoCalendar,
+    #[error("not an iCalendar file, expected BEGIN:VCALENDAR to END:VCALENDAR")]
+    NotCalendar,
+    #[error("invalid ramp resolution: `{0}`, expected at least 60 seconds")]
+    InvalidRamp(u32),
+    #[error(
+        "invalid profile time: `{0}`, expected like \"sleep+0:30\", \"wake-1:00\" or \"03:00\""
+    )]
+    InvalidAnchor(String),
+    #[error("invalid profile point at `{0}`: {1}")]
+    InvalidProfilePoint(Anchor, &'static str),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Settings {
     #[serde(deserialize_with = "timezone_de", serialize_with = "timezone_ser")]
     pub timezone: TimeZone,
@@ -35,34 +75,122 @@ pub struct Settings {
     pub prime: Option<Time>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub led_brightness: Option<u8>,
+    #[serde(default, skip_serializing_if = "Gain::is_unset")]
+    pub gain: Gain,
+    ///seconds between state updates from Frank
+    #[serde(default = "default_state_interval")]
+    pub state_interval: u32,
+    #[serde(default)]
+    pub retry: RetrySettings,
+    #[serde(default)]
+    pub history: HistorySettings,
+    ///directory to record every byte sent to and from Frank in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxySettings>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub calendar: Option<CalendarSettings>,
     #[serde(flatten)]
     pub by_side: BySideSettings,
-    // TODO nap mode
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(untagged)]
 pub enum BySideSettings {
     Couples {
-        left: SideSettings,
-        right: SideSettings,
+        left: Box<SideSettings>,
+        right: Box<SideSettings>,
     },
     Solo {
-        both: SideSettings,
+        both: Box<SideSettings>,
     },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct SideSettings {
-    /// -10 -> 25.8°C
-    /// -50 -> 21
-    pub temp_profile: Vec<i16>,
+    /// heat levels (-10 -> 25.8°C, -50 -> 21°C) or temperatures, see [`Temp`]
+    pub temp_profile: Vec<ProfilePoint>,
+    /// moves smoothly between the profile's temperatures instead of stepping
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ramp: Option<Ramp>,
     pub sleep: Time,
     pub wake: Time,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub vibration: Option<VibrationAlarm>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub heat: Option<HeatAlarm>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temp_override: Option<TempOverride>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nap: Option<Nap>,
+    /// nights that differ from the above, by the day they wake up on
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub weekly: BTreeMap<Weekday, DaySettings>,
+    /// dated changes on top of the weekly schedule, the latest wins
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exceptions: Vec<ScheduleException>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Ramp {
+    pub curve: RampCurve,
+    ///seconds between temperature updates
+    #[serde(default = "default_ramp_resolution")]
+    pub resolution: u32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RampCurve {
+    Linear,
+    ///eases in and out of each temperature
+    Cosine,
+}
+
+/// One night of the week, replacing the side's own sleep, wake and alarms
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DaySettings {
+    ///the side's own profile if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temp_profile: Option<Vec<ProfilePoint>>,
+    pub sleep: Time,
+    pub wake: Time,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vibration: Option<VibrationAlarm>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heat: Option<HeatAlarm>,
+}
+
+/// Skips or moves the nights that wake up from `from` to `to`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ScheduleException {
+    ///assigned when loaded or added through the API
+    #[serde(default)]
+    pub id: u32,
+    pub from: Date,
+    ///inclusive, just `from` if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<Date>,
+    ///no schedule at all for these nights
+    #[serde(default)]
+    pub skip: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sleep: Option<Time>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wake: Option<Time>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -85,13 +213,99 @@ pub enum VibrationPattern {
     Rise,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct HeatAlarm {
-    pub temp: i16,
+    pub temp: Temp,
     ///seconds before sleep
     pub offset: u16,
 }
 
+/// A temperature set by hand, which pauses the
+/// temperature profile for the side until it ends
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TempOverride {
+    pub temp: Temp,
+    pub until: Timestamp,
+}
+
+/// A nap on top of the nightly schedule, which pauses the
+/// temperature profile for the side while it lasts
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Nap {
+    pub start: Timestamp,
+    pub minutes: u16,
+    pub temp: Temp,
+    ///an alarm at the end of the nap, `offset` before it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vibration: Option<VibrationAlarm>,
+}
+
+/// Sensor gain for each side, left as Frank has it until set
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Gain {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub left: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub right: Option<u16>,
+}
+
+/// How failed Frank commands are retried, for each kind of command
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct RetrySettings {
+    pub set_temp: RetryPolicy,
+    pub set_alarm: RetryPolicy,
+    pub set_settings: RetryPolicy,
+    pub clear_alarm: RetryPolicy,
+    pub prime: RetryPolicy,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RetryPolicy {
+    ///retries after the first failure, 0 to never retry
+    pub attempts: u8,
+    ///seconds before the first retry, doubling after each
+    pub delay: u32,
+    ///seconds, limit for the doubling delay
+    pub max_delay: u32,
+}
+
+/// How much of Frank's state history to keep
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct HistorySettings {
+    ///hours of history to keep, 0 to keep none. Only the newest
+    ///50,000 samples are kept, about 6 days at the fastest `state_interval`
+    pub retention: u32,
+    ///where history is saved, only read on startup, changes apply after a restart
+    pub file: String,
+}
+
+/// Sits between Frank and the stock DAC instead of replacing it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ProxySettings {
+    ///socket the stock DAC was moved to
+    pub dac_socket: String,
+    ///commands from the stock DAC that are answered "ok" without reaching Frank
+    #[serde(default)]
+    pub blocked: Vec<u8>,
+}
+
+/// Turns calendar events into schedule exceptions, by their
+/// summary containing a keyword or having it as a category
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct CalendarSettings {
+    ///.ics file to read, uploaded calendars are saved here
+    pub file: String,
+    ///skips the nights during these events
+    pub away: Vec<String>,
+    ///wakes up `wake_before` these events start
+    pub early: Vec<String>,
+    ///seconds
+    pub wake_before: u32,
+}
+
 impl Settings {
     pub fn from_file(path: &str) -> Result<Self, SettingsError> {
         let file_contents = fs::read_to_string(path)?;
@@ -99,7 +313,59 @@ impl Settings {
     }
 
     pub fn from_str(json: &str) -> Result<Self, SettingsError> {
-        Ok(serde_json::from_str(json)?)
+        let mut settings: Self = serde_json::from_str(json)?;
+        settings.number_exceptions();
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Checks what serde can't
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        self.gain.validate()?;
+        for (_, side) in self.sides() {
+            side.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Gives exceptions without an id, or with one already taken,
+    /// an id of their own so each can be removed through the API
+    pub fn number_exceptions(&mut self) {
+        for (_, side) in self.sides_mut() {
+            let mut next = side.exceptions.iter().map(|e| e.id).max().unwrap_or(0);
+            let mut taken = BTreeSet::new();
+            for exception in &mut side.exceptions {
+                if exception.id == 0 || !taken.insert(exception.id) {
+                    next += 1;
+                    exception.id = next;
+                }
+            }
+        }
+    }
+
+    /// Removes exceptions that ended before `today`,
+    /// returning whether there were any
+    pub fn drop_expired(&mut self, today: Date) -> bool {
+        let mut dropped = false;
+        for (_, side) in self.sides_mut() {
+            let before = side.exceptions.len();
+            side.exceptions.retain(|e| !e.expired(today));
+            dropped |= side.exceptions.len() != before;
+        }
+        dropped
+    }
+
+    /// Removes naps that ended by `now`,
+    /// returning whether there were any
+    pub fn drop_ended_naps(&mut self, now: Timestamp) -> bool {
+        let mut dropped = false;
+        for (_, side) in self.sides_mut() {
+            if side.nap.as_ref().is_some_and(|nap| nap.end() <= now) {
+                side.nap = None;
+                dropped = true;
+            }
+        }
+        dropped
     }
 
     pub fn serialize(&self) -> Result<String, SettingsError> {
@@ -138,15 +404,275 @@ impl Settings {
             _ => Err(SettingsError::NotCouples),
         }
     }
+
+    /// The settings for each side in the current mode
+    pub fn sides(&self) -> Vec<(SideTarget, &SideSettings)> {
+        match &self.by_side {
+            BySideSettings::Couples { left, right } => {
+                vec![(SideTarget::Left, left), (SideTarget::Right, right)]
+            }
+            BySideSettings::Solo { both } => vec![(SideTarget::Both, both)],
+        }
+    }
+
+    /// See [`Self::sides`]
+    pub fn sides_mut(&mut self) -> Vec<(SideTarget, &mut SideSettings)> {
+        match &mut self.by_side {
+            BySideSettings::Couples { left, right } => {
+                vec![(SideTarget::Left, left), (SideTarget::Right, right)]
+            }
+            BySideSettings::Solo { both } => vec![(SideTarget::Both, both)],
+        }
+    }
+
+    /// See [`Self::side`]
+    pub fn side_mut(&mut self, side: &SideTarget) -> Result<&mut SideSettings, SettingsError> {
+        match side {
+            SideTarget::Left => Ok(self.as_couples_mut()?.0),
+            SideTarget::Right => Ok(self.as_couples_mut()?.1),
+            SideTarget::Both => self.as_solo_mut(),
+        }
+    }
+
+    /// The settings for `side`, if it matches the current mode
+    pub fn side(&self, side: &SideTarget) -> Result<&SideSettings, SettingsError> {
+        match side {
+            SideTarget::Left => Ok(self.as_couples()?.0),
+            SideTarget::Right => Ok(self.as_couples()?.1),
+            SideTarget::Both => self.as_solo(),
+        }
+    }
+}
+
+impl SideSettings {
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        let heat = self.heat.as_ref().map(|heat| &heat.temp);
+        let temp_override = self.temp_override.as_ref().map(|o| &o.temp);
+        let nap = self.nap.as_ref().map(|nap| &nap.temp);
+        let days = self.weekly.values().flat_map(|day| {
+            let heat = day.heat.as_ref().map(|heat| &heat.temp);
+            let profile = day.temp_profile.iter().flatten().map(ProfilePoint::temp);
+            profile.chain(heat)
+        });
+        let mut temps = self
+            .temp_profile
+            .iter()
+            .map(ProfilePoint::temp)
+            .chain(heat)
+            .chain(temp_override)
+            .chain(nap)
+            .chain(days);
+        if let Some(temp) = temps.find(|t| !t.in_range()) {
+            return Err(SettingsError::InvalidTemp(*temp));
+        }
+        if let Some(ramp) = &self.ramp
+            && ramp.resolution < 60
+        {
+            return Err(SettingsError::InvalidRamp(ramp.resolution));
+        }
+        if self.nap.as_ref().is_some_and(|nap| nap.minutes == 0) {
+            return Err(SettingsError::InvalidNap("`minutes` must be more than 0"));
+        }
+        if let Some(nap) = &self.nap
+            && nap.checked_end().is_none()
+        {
+            return Err(SettingsError::InvalidNap("it ends too late"));
+        }
+        profile::validate(&self.temp_profile, self.sleep, self.wake)?;
+        for day in self.weekly.values() {
+            let temp_profile = day.temp_profile.as_ref().unwrap_or(&self.temp_profile);
+            profile::validate(temp_profile, day.sleep, day.wake)?;
+        }
+        self.exceptions
+            .iter()
+            .try_for_each(ScheduleException::validate)
+    }
+
+    /// The settings for the night that wakes up on `date`
+    pub fn on(&self, date: Date) -> SideSettings {
+        let mut cfg = self.clone();
+        cfg.weekly.clear();
+        cfg.exceptions.clear();
+        if let Some(night) = self.weekly.get(&date.weekday().into()) {
+            if let Some(temp_profile) = &night.temp_profile {
+                cfg.temp_profile = temp_profile.clone();
+            }
+            cfg.sleep = night.sleep;
+            cfg.wake = night.wake;
+            cfg.vibration = night.vibration.clone();
+            cfg.heat = night.heat.clone();
+        }
+        if let Some(exception) = self.exception(date) {
+            cfg.sleep = exception.sleep.unwrap_or(cfg.sleep);
+            cfg.wake = exception.wake.unwrap_or(cfg.wake);
+        }
+        cfg
+    }
+
+    /// Whether the night that wakes up on `date` is skipped
+    pub fn skips(&self, date: Date) -> bool {
+        self.exception(date).is_some_and(|e| e.skip)
+    }
+
+    fn exception(&self, date: Date) -> Option<&ScheduleException> {
+        self.exceptions.iter().rev().find(|e| e.covers(date))
+    }
+
+    /// The override, unless it has already ended
+    pub fn active_override(&self, now: Timestamp) -> Option<&TempOverride> {
+        self.temp_override.as_ref().filter(|o| o.until > now)
+    }
+
+    /// The nap, unless it has already ended
+    pub fn active_nap(&self, now: Timestamp) -> Option<&Nap> {
+        self.nap.as_ref().filter(|nap| nap.end() > now)
+    }
+}
+
+impl Nap {
+    /// Stops at the end of time, which
+    /// [`SideSettings::validate`] doesn't allow
+    pub fn end(&self) -> Timestamp {
+        self.checked_end().unwrap_or(Timestamp::MAX)
+    }
+
+    fn checked_end(&self) -> Option<Timestamp> {
+        let length = SignedDuration::from_mins(self.minutes.into());
+        self.start.checked_add(length).ok()
+    }
+}
+
+impl ScheduleException {
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        if self.last_day() < self.from {
+            return Err(SettingsError::InvalidException("`to` is before `from`"));
+        }
+        match (self.skip, self.sleep.is_some() || self.wake.is_some()) {
+            (true, true) => Err(SettingsError::InvalidException(
+                "a skipped night has no `sleep` or `wake`",
+            )),
+            (false, false) => Err(SettingsError::InvalidException(
+                "expected `skip`, `sleep` or `wake`",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn last_day(&self) -> Date {
+        self.to.unwrap_or(self.from)
+    }
+
+    pub fn covers(&self, date: Date) -> bool {
+        (self.from..=self.last_day()).contains(&date)
+    }
+
+    /// Whether all of its nights are over by `today`
+    pub fn expired(&self, today: Date) -> bool {
+        self.last_day() < today
+    }
+}
+
+impl Gain {
+    pub fn is_unset(&self) -> bool {
+        self.left.is_none() && self.right.is_none()
+    }
+
+    /// Frank ships with a gain of 400 and there is no known upper limit, but 0 is never usable
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        if self.left == Some(0) || self.right == Some(0) {
+            return Err(SettingsError::InvalidGain(0));
+        }
+        Ok(())
+    }
+
+    /// `side`'s gain, for `both` only when the sides match
+    pub fn get(&self, side: &SideTarget) -> Option<u16> {
+        match side {
+            SideTarget::Left => self.left,
+            SideTarget::Right => self.right,
+            SideTarget::Both => self.left.filter(|_| self.left == self.right),
+        }
+    }
+
+    pub fn set(&mut self, side: &SideTarget, gain: u16) {
+        match side {
+            SideTarget::Left => self.left = Some(gain),
+            SideTarget::Right => self.right = Some(gain),
+            SideTarget::Both => (self.left, self.right) = (Some(gain), Some(gain)),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub const NEVER: Self = Self {
+        attempts: 0,
+        delay: 0,
+        max_delay: 0,
+    };
+
+    const BACKOFF: Self = Self {
+        attempts: 3,
+        delay: 30,
+        max_delay: 300,
+    };
 }
 
-impl VibrationPattern {
-    pub fn to_string(&self) -> String {
-        match self {
+impl Default for RetrySettings {
+    fn default() -> Self {
+        Self {
+            set_temp: RetryPolicy::BACKOFF,
+            set_alarm: RetryPolicy::BACKOFF,
+            set_settings: RetryPolicy::BACKOFF,
+            clear_alarm: RetryPolicy::BACKOFF,
+            // priming twice in a row is never what you want
+            prime: RetryPolicy::NEVER,
+        }
+    }
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        Self {
+            // a week
+            retention: 168,
+            file: "history.jsonl".to_string(),
+        }
+    }
+}
+
+impl From<jiff::civil::Weekday> for Weekday {
+    fn from(day: jiff::civil::Weekday) -> Self {
+        use jiff::civil::Weekday as W;
+        match day {
+            W::Monday => Self::Monday,
+            W::Tuesday => Self::Tuesday,
+            W::Wednesday => Self::Wednesday,
+            W::Thursday => Self::Thursday,
+            W::Friday => Self::Friday,
+            W::Saturday => Self::Saturday,
+            W::Sunday => Self::Sunday,
+        }
+    }
+}
+
+impl Default for CalendarSettings {
+    fn default() -> Self {
+        Self {
+            file: "calendar.ics".to_string(),
+            away: vec!["away".to_string()],
+            early: vec!["early".to_string()],
+            // an hour
+            wake_before: 3600,
+        }
+    }
+}
+
+impl fmt::Display for VibrationPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
             VibrationPattern::Double => "double",
             VibrationPattern::Rise => "rise",
-        }
-        .to_string()
+        })
     }
 }
 
@@ -162,6 +688,14 @@ impl FromStr for VibrationPattern {
     }
 }
 
+fn default_state_interval() -> u32 {
+    1200
+}
+
+fn default_ramp_resolution() -> u32 {
+    300
+}
+
 fn timezone_de<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TimeZone, D::Error> {
     let tzname = String::deserialize(deserializer)?;
     TimeZone::get(&tzname)
@@ -184,10 +718,19 @@ impl ResponseError for SettingsError {}
 
 #[cfg(test)]
 mod tests {
-    use jiff::{civil::time, tz::TimeZone};
+    use jiff::{
+        civil::{date, time},
+        tz::TimeZone,
+    };
 
-    use crate::settings::{
-        BySideSettings, HeatAlarm, Settings, SideSettings, VibrationAlarm, VibrationPattern,
+    use crate::{
+        frank::command::SideTarget,
+        profile::Anchor,
+        settings::{
+            BySideSettings, Gain, HeatAlarm, Ramp, RampCurve, Settings, SettingsError,
+            SideSettings, VibrationAlarm, VibrationPattern,
+        },
+        temp::Temp,
     };
 
     #[test]
@@ -223,9 +766,21 @@ mod tests {
             away_mode: false,
             prime: Some(time(15, 0, 0, 0)),
             led_brightness: Some(100),
+            gain: Gain::default(),
+            state_interval: 1200,
+            retry: Default::default(),
+            history: Default::default(),
+            trace_dir: None,
+            proxy: None,
+            calendar: None,
             by_side: BySideSettings::Solo {
-                both: SideSettings {
-                    temp_profile: vec![-10, 10, 20],
+                both: Box::new(SideSettings {
+                    temp_profile: vec![
+                        Temp::Level(-10).into(),
+                        Temp::Level(10).into(),
+                        Temp::Level(20).into(),
+                    ],
+                    ramp: None,
                     sleep: time(22, 0, 0, 0),
                     wake: time(10, 30, 0, 0),
                     vibration: Some(VibrationAlarm {
@@ -235,10 +790,14 @@ mod tests {
                         offset: 300,
                     }),
                     heat: Some(HeatAlarm {
-                        temp: 100,
+                        temp: Temp::Level(100),
                         offset: 1800,
                     }),
-                },
+                    temp_override: None,
+                    nap: None,
+                    weekly: Default::default(),
+                    exceptions: vec![],
+                }),
             },
         };
 
@@ -289,7 +848,12 @@ mod tests {
         .unwrap();
 
         let s = SideSettings {
-            temp_profile: vec![-10, 10, 20],
+            temp_profile: vec![
+                Temp::Level(-10).into(),
+                Temp::Level(10).into(),
+                Temp::Level(20).into(),
+            ],
+            ramp: None,
             sleep: time(22, 0, 0, 0),
             wake: time(10, 30, 0, 0),
             vibration: Some(VibrationAlarm {
@@ -299,9 +863,13 @@ mod tests {
                 offset: 300,
             }),
             heat: Some(HeatAlarm {
-                temp: 100,
+                temp: Temp::Level(100),
                 offset: 1800,
             }),
+            temp_override: None,
+            nap: None,
+            weekly: Default::default(),
+            exceptions: vec![],
         };
 
         let b = Settings {
@@ -309,12 +877,258 @@ mod tests {
             away_mode: false,
             prime: Some(time(15, 0, 0, 0)),
             led_brightness: Some(100),
+            gain: Gain::default(),
+            state_interval: 1200,
+            retry: Default::default(),
+            history: Default::default(),
+            trace_dir: None,
+            proxy: None,
+            calendar: None,
             by_side: BySideSettings::Couples {
-                left: s.clone(),
-                right: s,
+                left: Box::new(s.clone()),
+                right: Box::new(s),
             },
         };
 
         assert_eq!(a, b);
     }
+
+    /// Solo settings sleeping 22:00 to 07:00, with `fields` added to the side
+    fn solo(fields: &str) -> Result<Settings, SettingsError> {
+        Settings::from_str(&format!(
+            r#"{{
+                "timezone": "America/New_York",
+                "both": {{ "sleep": "22:00", "wake": "07:00", {fields} }}
+            }}"#
+        ))
+    }
+
+    #[test]
+    fn test_gain() {
+        let mut settings = Settings::from_str(
+            r#"
+            {
+                "timezone": "America/New_York",
+                "gain": { "left": 350 },
+                "both": { "temp_profile": [0], "sleep": "22:00", "wake": "07:00" }
+            }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.gain,
+            Gain {
+                left: Some(350),
+                right: None
+            }
+        );
+        assert_eq!(settings.gain.get(&SideTarget::Both), None);
+
+        settings.gain.set(&SideTarget::Both, 420);
+        assert_eq!(settings.gain.get(&SideTarget::Both), Some(420));
+        assert!(!settings.gain.is_unset());
+
+        settings.gain.set(&SideTarget::Right, 0);
+        assert!(matches!(
+            settings.validate(),
+            Err(SettingsError::InvalidGain(0))
+        ));
+    }
+
+    #[test]
+    fn test_temp_units() {
+        let settings = solo(
+            r#"
+            "temp_profile": [-10, { "c": 21 }, { "f": 70 }],
+            "heat": { "temp": { "f": 80 }, "offset": 600 }
+            "#,
+        )
+        .unwrap();
+        let both = settings.as_solo().unwrap();
+        let levels: Vec<_> = both.temp_profile.iter().map(|p| p.temp().level()).collect();
+        assert_eq!(levels, vec![-10, -50, -49]);
+        assert_eq!(both.heat.as_ref().unwrap().temp.level(), -3);
+        // written back the way it was given
+        assert!(settings.serialize().unwrap().contains(r#"[-10,{"c":21.0},{"f":70.0}]"#));
+
+        assert!(matches!(
+            solo(r#""temp_profile": [-10, { "c": 50 }]"#),
+            Err(SettingsError::InvalidTemp(Temp::Celsius { c: 50. }))
+        ));
+        assert!(matches!(
+            solo(r#""temp_profile": [120]"#),
+            Err(SettingsError::InvalidTemp(Temp::Level(120)))
+        ));
+    }
+
+    #[test]
+    fn test_ramp() {
+        let settings = solo(r#""temp_profile": [-20, 20], "ramp": { "curve": "cosine" }"#).unwrap();
+        assert_eq!(
+            settings.as_solo().unwrap().ramp,
+            Some(Ramp {
+                curve: RampCurve::Cosine,
+                resolution: 300
+            })
+        );
+        assert!(matches!(
+            solo(r#""temp_profile": [0], "ramp": { "curve": "linear", "resolution": 10 }"#),
+            Err(SettingsError::InvalidRamp(10))
+        ));
+    }
+
+    #[test]
+    fn test_nap() {
+        let mut settings = solo(
+            r#"
+            "temp_profile": [0],
+            "nap": { "start": "2025-06-07T18:00:00Z", "minutes": 45, "temp": { "c": 21 } }
+            "#,
+        )
+        .unwrap();
+        let both = settings.as_solo().unwrap();
+        let nap = both.nap.as_ref().unwrap();
+        assert_eq!(nap.end(), "2025-06-07T18:45:00Z".parse().unwrap());
+        assert_eq!(nap.vibration, None);
+        // still going on before it starts
+        assert!(both
+            .active_nap("2025-06-07T17:00:00Z".parse().unwrap())
+            .is_some());
+        assert!(both.active_nap(nap.end()).is_none());
+
+        // ended naps are cleared from the settings
+        assert!(!settings.drop_ended_naps("2025-06-07T18:44:59Z".parse().unwrap()));
+        assert!(settings.drop_ended_naps("2025-06-07T18:45:00Z".parse().unwrap()));
+        assert_eq!(settings.as_solo().unwrap().nap, None);
+
+        assert!(matches!(
+            solo(
+                r#""temp_profile": [0], "nap": { "start": "2025-06-07T18:00:00Z", "minutes": 0, "temp": 0 }"#
+            ),
+            Err(SettingsError::InvalidNap(_))
+        ));
+        assert!(matches!(
+            solo(
+                r#""temp_profile": [0], "nap": { "start": "2025-06-07T18:00:00Z", "minutes": 20, "temp": 150 }"#
+            ),
+            Err(SettingsError::InvalidTemp(Temp::Level(150)))
+        ));
+        // one ending past the end of time
+        assert!(matches!(
+            solo(
+                r#""temp_profile": [0], "nap": { "start": "9999-12-30T21:30:00Z", "minutes": 60, "temp": 0 }"#
+            ),
+            Err(SettingsError::InvalidNap(_))
+        ));
+    }
+
+    #[test]
+    fn test_weekly() {
+        let settings = solo(
+            r#"
+            "temp_profile": [-10, 0],
+            "vibration": { "pattern": "rise", "intensity": 50, "duration": 60, "offset": 0 },
+            "weekly": { "saturday": { "sleep": "00:30", "wake": "10:00" } }
+            "#,
+        )
+        .unwrap();
+        let both = settings.as_solo().unwrap();
+        let saturday = both.on(date(2025, 6, 7));
+        assert_eq!(
+            (saturday.sleep, saturday.wake),
+            (time(0, 30, 0, 0), time(10, 0, 0, 0))
+        );
+        // no alarm, but the same profile
+        assert_eq!(saturday.vibration, None);
+        assert_eq!(saturday.temp_profile, both.temp_profile);
+        assert_eq!(both.on(date(2025, 6, 9)).wake, time(7, 0, 0, 0));
+
+        assert!(matches!(
+            solo(
+                r#"
+                "temp_profile": [0],
+                "weekly": { "saturday": { "temp_profile": [200], "sleep": "00:30", "wake": "10:00" } }
+                "#
+            ),
+            Err(SettingsError::InvalidTemp(Temp::Level(200)))
+        ));
+        // pinned before the day's bedtime
+        assert!(matches!(
+            solo(
+                r#"
+                "temp_profile": [0],
+                "weekly": { "saturday": {
+                    "temp_profile": [{ "at": "23:00", "temp": 0 }], "sleep": "00:30", "wake": "10:00"
+                } }
+                "#
+            ),
+            Err(SettingsError::InvalidProfilePoint(Anchor::At(_), _))
+        ));
+    }
+
+    #[test]
+    fn test_exceptions() {
+        let mut settings = solo(
+            r#"
+            "temp_profile": [0],
+            "exceptions": [
+                { "id": 1, "from": "2026-11-10", "to": "2026-11-17", "skip": true },
+                { "id": 2, "from": "2026-11-03", "wake": "05:30" },
+                { "id": 3, "from": "2026-11-12", "sleep": "23:00" }
+            ]
+            "#,
+        )
+        .unwrap();
+        let both = settings.as_solo().unwrap();
+        assert_eq!(both.on(date(2026, 11, 3)).wake, time(5, 30, 0, 0));
+        assert_eq!(both.on(date(2026, 11, 4)).wake, time(7, 0, 0, 0));
+        assert!(both.skips(date(2026, 11, 10)) && both.skips(date(2026, 11, 17)));
+        assert!(!both.skips(date(2026, 11, 18)));
+        // the latest exception wins
+        assert!(!both.skips(date(2026, 11, 12)));
+        assert_eq!(both.on(date(2026, 11, 12)).sleep, time(23, 0, 0, 0));
+
+        assert!(settings.drop_expired(date(2026, 11, 13)));
+        let ids: Vec<_> = settings
+            .as_solo()
+            .unwrap()
+            .exceptions
+            .iter()
+            .map(|e| e.id)
+            .collect();
+        assert_eq!(ids, vec![1]);
+        assert!(!settings.drop_expired(date(2026, 11, 13)));
+
+        // missing and repeated ids are numbered after the rest
+        let settings = solo(
+            r#"
+            "temp_profile": [0],
+            "exceptions": [
+                { "from": "2026-11-10", "skip": true },
+                { "id": 4, "from": "2026-11-03", "wake": "05:30" },
+                { "id": 4, "from": "2026-11-12", "sleep": "23:00" }
+            ]
+            "#,
+        )
+        .unwrap();
+        let ids: Vec<_> = settings
+            .as_solo()
+            .unwrap()
+            .exceptions
+            .iter()
+            .map(|e| e.id)
+            .collect();
+        assert_eq!(ids, vec![5, 4, 6]);
+
+        for bad in [
+            r#"{ "from": "2026-11-10", "to": "2026-11-09", "skip": true }"#,
+            r#"{ "from": "2026-11-10", "skip": true, "wake": "05:30" }"#,
+            r#"{ "from": "2026-11-10" }"#,
+        ] {
+            assert!(matches!(
+                solo(&format!(r#""temp_profile": [0], "exceptions": [{bad}]"#)),
+                Err(SettingsError::InvalidException(_))
+            ));
+        }
+    }
 }
@@ -0,0 +1,152 @@
+//! Converting Frank's heat levels (-100 to 100) to and from real temperatures.
+//!
+//! Frank has no idea what temperature the water is, only how hard to
+//! heat or cool it. Only two heat levels have a measured water temperature,
+//! so everything else is an estimate on the line through them.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// (heat level, °C) measured at two levels
+const CALIBRATION: [(i16, f32); 2] = [(-50, 21.), (-10, 25.8)];
+
+/// A temperature as either a raw heat level, `{"c": 21.5}`, or `{"f": 70}`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Temp {
+    Level(i16),
+    Celsius { c: f32 },
+    Fahrenheit { f: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TempUnit {
+    Level,
+    C,
+    F,
+}
+
+impl Temp {
+    /// The heat level for this temperature, clamped to what Frank can do
+    pub fn level(&self) -> i16 {
+        match *self {
+            Temp::Level(level) => level.clamp(-100, 100),
+            Temp::Celsius { c } => c_to_level(c),
+            Temp::Fahrenheit { f } => c_to_level(f_to_c(f)),
+        }
+    }
+
+    /// Whether this is a temperature Frank can reach, going by the estimate
+    pub fn in_range(&self) -> bool {
+        let (min, max) = (level_to_c(-100), level_to_c(100));
+        match *self {
+            Temp::Level(level) => (-100..=100).contains(&level),
+            Temp::Celsius { c } => (min..=max).contains(&c),
+            Temp::Fahrenheit { f } => (min..=max).contains(&f_to_c(f)),
+        }
+    }
+
+    /// Describes `level` in `unit`, to one decimal place
+    pub fn from_level(level: i16, unit: TempUnit) -> Self {
+        let round = |t: f32| (t * 10.).round() / 10.;
+        match unit {
+            TempUnit::Level => Temp::Level(level),
+            TempUnit::C => Temp::Celsius {
+                c: round(level_to_c(level)),
+            },
+            TempUnit::F => Temp::Fahrenheit {
+                f: round(c_to_f(level_to_c(level))),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Temp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Temp::Level(level) => write!(f, "{level}"),
+            Temp::Celsius { c } => write!(f, "{c}°C"),
+            Temp::Fahrenheit { f: t } => write!(f, "{t}°F"),
+        }
+    }
+}
+
+impl From<i16> for Temp {
+    fn from(level: i16) -> Self {
+        Temp::Level(level)
+    }
+}
+
+/// Estimated water temperature at `level`, about 15-39°C
+pub fn level_to_c(level: i16) -> f32 {
+    let level = level.clamp(-100, 100);
+    let [(l0, c0), (l1, c1)] = CALIBRATION;
+    c0 + (c1 - c0) * f32::from(level - l0) / f32::from(l1 - l0)
+}
+
+pub fn c_to_level(c: f32) -> i16 {
+    let [(l0, c0), (l1, c1)] = CALIBRATION;
+    let level = f32::from(l0) + f32::from(l1 - l0) * (c - c0) / (c1 - c0);
+    level.round().clamp(-100., 100.) as i16
+}
+
+pub fn c_to_f(c: f32) -> f32 {
+    c * 9. / 5. + 32.
+}
+
+pub fn f_to_c(f: f32) -> f32 {
+    (f - 32.) * 5. / 9.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{c_to_level, level_to_c, Temp, TempUnit};
+
+    #[test]
+    fn test_calibration_points() {
+        assert_eq!(level_to_c(-10), 25.8);
+        assert_eq!(level_to_c(-50), 21.);
+        assert_eq!(level_to_c(-30), 23.4);
+        // the ends are estimated from the line through those
+        assert!((level_to_c(-100) - 15.).abs() < 0.01);
+        assert!((level_to_c(100) - 39.).abs() < 0.01);
+
+        assert_eq!(c_to_level(25.8), -10);
+        assert_eq!(c_to_level(21.), -50);
+        assert_eq!(c_to_level(5.), -100);
+        assert_eq!(c_to_level(50.), 100);
+
+        for level in -100..=100 {
+            assert_eq!(c_to_level(level_to_c(level)), level);
+        }
+    }
+
+    #[test]
+    fn test_temp_forms() {
+        let temps: Vec<Temp> = serde_json::from_str(r#"[-10, {"c": 21}, {"f": 70}]"#).unwrap();
+        assert_eq!(
+            temps,
+            vec![
+                Temp::Level(-10),
+                Temp::Celsius { c: 21. },
+                Temp::Fahrenheit { f: 70. }
+            ]
+        );
+        let levels: Vec<_> = temps.iter().map(Temp::level).collect();
+        assert_eq!(levels, vec![-10, -50, -49]);
+
+        // raw levels serialize as before
+        assert_eq!(serde_json::to_string(&temps[0]).unwrap(), "-10");
+        assert_eq!(
+            serde_json::to_string(&Temp::from_level(-10, TempUnit::F)).unwrap(),
+            r#"{"f":78.4}"#
+        );
+
+        assert!(!Temp::Level(101).in_range());
+        assert!(!Temp::Celsius { c: 40. }.in_range());
+        assert!(!Temp::Celsius { c: 14. }.in_range());
+        assert!(Temp::Fahrenheit { f: 70. }.in_range());
+    }
+}
@@ -1,6 +1,787 @@
 mod tests {
-    #[test]
-    fn global() {
-        // TODO global testing (requires frank sim)
+    use std::{future::Future, time::Duration};
+
+    use actix_web::{
+        http::StatusCode,
+        test::{call_and_read_body, call_service, init_service, read_body, TestRequest},
+        web::Data,
+        App,
+    };
+    use jiff::{tz::TimeZone, SignedDuration, Timestamp};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{UnixListener, UnixStream},
+        sync::watch,
+        task::yield_now,
+        time::{advance, sleep},
+    };
+
+    use crate::{
+        api,
+        frank::{
+            self,
+            command::{
+                FrankCommand, SideTarget, ALARM_CLEAR, HELLO, PRIME, STATUS, TEMP_LEFT, TEMP_RIGHT,
+            },
+            error::FrankError,
+            sim::FrankSim,
+            trace,
+            vibration::TimestampedVibrationAlarm,
+        },
+        scheduler,
+        settings::{
+            CalendarSettings, Nap, ProxySettings, ScheduleException, Settings, TempOverride,
+            VibrationAlarm, VibrationPattern,
+        },
+        temp::Temp,
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn socket_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("opensleep-{name}-{}.sock", std::process::id()))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// Settings that don't schedule anything, with history kept out of the repo
+    fn away_settings(name: &str) -> Settings {
+        let mut settings = Settings::from_str(include_str!("../example_solo.json")).unwrap();
+        settings.away_mode = true;
+        settings.history.file = std::env::temp_dir()
+            .join(format!("opensleep-{name}-{}.jsonl", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let _ = std::fs::remove_file(&settings.history.file);
+        settings
+    }
+
+    /// Sends a request as the stock DAC would, returning Frank's response
+    async fn dac_request(dac: &mut UnixStream, req: &str) -> String {
+        dac.write_all(req.as_bytes()).await.unwrap();
+        let mut res = Vec::new();
+        while !res.ends_with(b"\n\n") {
+            res.push(dac.read_u8().await.unwrap());
+        }
+        res.truncate(res.len() - 2);
+        String::from_utf8(res).unwrap()
+    }
+
+    /// Polls `f` until it returns true, panicking after a few seconds
+    async fn eventually<F: Future<Output = bool>>(mut f: impl FnMut() -> F) {
+        for _ in 0..500 {
+            if f().await {
+                return;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition never became true");
+    }
+
+    #[actix_web::test]
+    async fn global() {
+        let path = socket_path("global");
+        let sim = FrankSim::new();
+        sim.connect(&path);
+
+        let mut settings = away_settings("global");
+        settings.led_brightness = Some(42);
+        let (settings_tx, settings_rx) = watch::channel(settings);
+        let (frank, frank_state, history) = frank::run(&path, settings_rx.clone()).await.unwrap();
+
+        // first STATUS happens right away
+        eventually(|| async { frank_state.read().await.valid }).await;
+        assert_eq!(
+            frank_state.read().await.sensor_label,
+            "20600-0001-F00-0001089C"
+        );
+
+        frank
+            .exec(FrankCommand::SetTemp(SideTarget::Left, 30, 600), TIMEOUT)
+            .await
+            .unwrap();
+        let snapshot = sim.snapshot();
+        assert_eq!(snapshot.left.tar, 30);
+        assert!(snapshot.left.time > 599.);
+        assert_eq!(snapshot.right.tar, 0);
+
+        // water warms up over time
+        sim.advance(Duration::from_secs(60));
+        assert!(sim.snapshot().left.cur > snapshot.left.cur + 9.);
+
+        // Frank refuses out of range temperatures
+        let res = frank
+            .exec(FrankCommand::SetTemp(SideTarget::Left, 500, 600), TIMEOUT)
+            .await;
+        assert!(matches!(res, Err(FrankError::ExpectedOk(s)) if s == "error"));
+        assert_eq!(sim.snapshot().left.tar, 30);
+
+        // scheduler pushes LED brightness on start, keeping Frank's gain
+        sim.state.lock().unwrap().settings.gain_left = 350;
+        actix_web::rt::spawn(scheduler::run(frank.clone(), settings_rx.clone()));
+        eventually(|| async { sim.snapshot().settings.led_brightness_perc == 42 }).await;
+        assert_eq!(sim.snapshot().settings.gain_left, 350);
+        assert_eq!(sim.snapshot().settings.gain_right, 400);
+
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(frank.clone()))
+                .app_data(Data::new(frank_state.clone()))
+                .app_data(Data::new(history))
+                .app_data(Data::new(settings_rx))
+                .app_data(Data::new(settings_tx))
+                .configure(api::configure),
+        )
+        .await;
+
+        let res = call_service(&app, TestRequest::get().uri("/health").to_request()).await;
+        assert!(res.status().is_success());
+
+        let body = call_and_read_body(&app, TestRequest::get().uri("/state").to_request()).await;
+        let state: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(state["valid"], true);
+        assert_eq!(state["water_level"], true);
+
+        let body = call_and_read_body(
+            &app,
+            TestRequest::get().uri("/state?unit=f").to_request(),
+        )
+        .await;
+        let state: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(state["cur_temp"]["left"]["f"].is_number());
+
+        // every STATUS so far is in the history
+        let body = call_and_read_body(
+            &app,
+            TestRequest::get().uri("/history?side=left").to_request(),
+        )
+        .await;
+        let history: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert!(!history.is_empty());
+        assert_eq!(history.last().unwrap()["tar_temp"], 30);
+        let body = call_and_read_body(
+            &app,
+            TestRequest::get()
+                .uri("/history?from=2000-01-01T00:00:00Z&to=2000-01-02T00:00:00Z")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(body, "[]");
+
+        let body =
+            call_and_read_body(&app, TestRequest::get().uri("/led_brightness").to_request()).await;
+        assert_eq!(body, "42");
+
+        let req = TestRequest::post()
+            .uri("/left/gain")
+            .set_json(serde_json::json!(-1))
+            .to_request();
+        assert!(call_service(&app, req).await.status().is_client_error());
+        let req = TestRequest::post()
+            .uri("/left/gain")
+            .set_json(serde_json::json!(0))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(String::from_utf8_lossy(&read_body(res).await).starts_with("invalid gain"));
+        let req = TestRequest::post()
+            .uri("/gain")
+            .set_json(serde_json::json!({ "left": 350, "right": 0 }))
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+        let body = call_and_read_body(&app, TestRequest::get().uri("/gain").to_request()).await;
+        assert_eq!(body, "{}");
+        let body =
+            call_and_read_body(&app, TestRequest::get().uri("/left/gain").to_request()).await;
+        assert_eq!(body, "null");
+
+        // concurrent refreshes share one STATUS round trip
+        let statuses = || {
+            sim.snapshot()
+                .log
+                .iter()
+                .filter(|(c, _)| *c == STATUS)
+                .count()
+        };
+        let before = statuses();
+        let (a, b, c) = tokio::join!(
+            frank.refresh(TIMEOUT),
+            frank.refresh(TIMEOUT),
+            call_and_read_body(&app, TestRequest::post().uri("/state/refresh").to_request()),
+        );
+        assert_eq!(statuses(), before + 1);
+        let (a, b) = (a.unwrap(), b.unwrap());
+        assert!(a.captured_at.is_some());
+        assert_eq!(a, b);
+        let c: serde_json::Value = serde_json::from_slice(&c).unwrap();
+        assert_eq!(c["captured_at"], a.captured_at.unwrap().to_string());
+        assert_eq!(*frank_state.read().await, a);
+    }
+
+    #[actix_web::test]
+    async fn reconnect() {
+        let path = socket_path("reconnect");
+        let sim = FrankSim::new();
+        let conn = sim.connect(&path);
+
+        let (_, settings_rx) = watch::channel(away_settings("reconnect"));
+        let (frank, frank_state, _) = frank::run(&path, settings_rx).await.unwrap();
+        eventually(|| async { frank_state.read().await.valid }).await;
+
+        conn.abort();
+        eventually(|| async { !frank_state.read().await.valid }).await;
+        assert!(frank_state.read().await.disconnected_since.is_some());
+
+        // caller gives up before a new Frank shows up
+        let res = frank
+            .exec(
+                FrankCommand::SetTemp(SideTarget::Right, 90, 60),
+                Duration::from_millis(50),
+            )
+            .await;
+        assert!(matches!(res, Err(FrankError::CommandTimeout)));
+
+        // queued while nobody is listening
+        let pending = frank
+            .submit(FrankCommand::SetTemp(SideTarget::Right, 20, 60), TIMEOUT)
+            .unwrap();
+
+        let sim = FrankSim::new();
+        sim.connect(&path);
+        pending.wait().await.unwrap();
+        assert_eq!(sim.snapshot().right.tar, 20);
+        // expired command was never sent
+        assert!(!sim
+            .snapshot()
+            .log
+            .contains(&(TEMP_RIGHT, Some("90".to_string()))));
+        eventually(|| async { frank_state.read().await.valid }).await;
+        assert!(frank_state.read().await.disconnected_since.is_none());
+    }
+
+    /// Lets the other tasks run until they're all waiting on
+    /// something, without paused time moving on by itself
+    async fn settle() {
+        for _ in 0..100 {
+            yield_now().await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry() {
+        let path = socket_path("retry");
+        let sim = FrankSim::new();
+        sim.connect(&path);
+
+        let (_, settings_rx) = watch::channel(away_settings("retry"));
+        let (frank, _, _) = frank::run(&path, settings_rx).await.unwrap();
+        let count = |cmd: u8| sim.snapshot().log.iter().filter(|(c, _)| *c == cmd).count();
+
+        // SetTemp is retried with backoff until Frank gives in
+        sim.fail_next(TEMP_LEFT, 2);
+        let cmd = FrankCommand::SetTemp(SideTarget::Left, 10, 60);
+        let exec = tokio::spawn({
+            let frank = frank.clone();
+            async move { frank.exec(cmd, Duration::from_secs(600)).await }
+        });
+        settle().await;
+        assert_eq!(count(TEMP_LEFT), 1);
+
+        // 30 seconds, then 60
+        advance(Duration::from_secs(29)).await;
+        settle().await;
+        assert_eq!(count(TEMP_LEFT), 1);
+        advance(Duration::from_secs(1)).await;
+        settle().await;
+        assert_eq!(count(TEMP_LEFT), 2);
+        advance(Duration::from_secs(59)).await;
+        settle().await;
+        assert_eq!(count(TEMP_LEFT), 2);
+        advance(Duration::from_secs(1)).await;
+        settle().await;
+        assert_eq!(count(TEMP_LEFT), 3);
+
+        exec.await.unwrap().unwrap();
+        assert_eq!(sim.snapshot().left.tar, 10);
+
+        // Prime is never retried
+        sim.fail_next(PRIME, 1);
+        let res = frank
+            .exec(FrankCommand::Prime, Duration::from_secs(600))
+            .await;
+        assert!(matches!(res, Err(FrankError::ExpectedOk(_))));
+        assert_eq!(count(PRIME), 1);
+    }
+
+    #[actix_web::test]
+    async fn trace_replay() {
+        let path = socket_path("trace");
+        let dir = std::env::temp_dir().join(format!("opensleep-trace-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let sim = FrankSim::new();
+        sim.connect(&path);
+
+        let mut settings = away_settings("trace");
+        settings.trace_dir = Some(dir.to_string_lossy().to_string());
+        let (_, settings_rx) = watch::channel(settings);
+        let (frank, frank_state, _) = frank::run(&path, settings_rx).await.unwrap();
+        eventually(|| async { frank_state.read().await.valid }).await;
+
+        frank
+            .exec(FrankCommand::SetTemp(SideTarget::Right, -30, 300), TIMEOUT)
+            .await
+            .unwrap();
+        let live = frank.refresh(TIMEOUT).await.unwrap();
+
+        let file = std::fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+        let replay = || async {
+            let entries = trace::load(file.path()).unwrap();
+            trace::replay(&entries).await.unwrap_or_default()
+        };
+        // written in the background, so wait for both STATUS requests
+        eventually(|| async { replay().await.len() == 2 }).await;
+
+        // which come back the same as they did live
+        let states = replay().await;
+        let replayed = states.last().unwrap();
+        assert_eq!(replayed.tar_temp.right, -30);
+        assert_eq!(replayed.cur_temp, live.cur_temp);
+        assert_eq!(replayed.settings, live.settings);
+    }
+
+    #[actix_web::test]
+    async fn proxy() {
+        let path = socket_path("proxy");
+        let dac_path = socket_path("proxy-dac");
+        let _ = std::fs::remove_file(&dac_path);
+        let stock_dac = UnixListener::bind(&dac_path).unwrap();
+        let sim = FrankSim::new();
+        sim.connect(&path);
+
+        let mut settings = away_settings("proxy");
+        settings.proxy = Some(ProxySettings {
+            dac_socket: dac_path,
+            blocked: vec![TEMP_LEFT],
+        });
+        let (_, settings_rx) = watch::channel(settings);
+        let (frank, _, _) = frank::run(&path, settings_rx).await.unwrap();
+        let (mut dac, _) = stock_dac.accept().await.unwrap();
+
+        // HELLO is answered for Frank, who we already greeted
+        assert_eq!(dac_request(&mut dac, "0\n\n").await, "ok");
+        let status = dac_request(&mut dac, "14\n\n").await;
+        assert!(status.contains("sensorLabel = \"20600-0001-F00-0001089C\""));
+
+        assert_eq!(dac_request(&mut dac, "10\n300\n\n").await, "ok");
+        assert_eq!(dac_request(&mut dac, "12\n-40\n\n").await, "ok");
+        assert_eq!(dac_request(&mut dac, "11\n-40\n\n").await, "ok");
+        assert_eq!(dac_request(&mut dac, "bogus\n\n").await, "error");
+
+        // our own commands are interleaved with the stock DAC's
+        frank
+            .exec(FrankCommand::SetTemp(SideTarget::Left, 15, 60), TIMEOUT)
+            .await
+            .unwrap();
+
+        let snapshot = sim.snapshot();
+        assert_eq!(snapshot.right.tar, -40);
+        assert_eq!(snapshot.left.tar, 15);
+        // blocked command never reached Frank
+        assert!(!snapshot.log.contains(&(TEMP_LEFT, Some("-40".to_string()))));
+        assert_eq!(snapshot.log.iter().filter(|(c, _)| *c == HELLO).count(), 1);
+    }
+
+    #[actix_web::test]
+    async fn alarms() {
+        let path = socket_path("alarms");
+        let sim = FrankSim::new();
+        sim.connect(&path);
+
+        let mut settings = away_settings("alarms");
+        let couples = Settings::from_str(include_str!("../example_couples.json")).unwrap();
+        settings.by_side = couples.by_side;
+        let vibration = settings.as_couples().unwrap().0.vibration.clone().unwrap();
+        let (_, settings_rx) = watch::channel(settings);
+        let (frank, _, _) = frank::run(&path, settings_rx.clone()).await.unwrap();
+
+        let at = (Timestamp::now() + SignedDuration::from_hours(1)).to_zoned(TimeZone::UTC);
+        for side in [SideTarget::Left, SideTarget::Right] {
+            let alarm = Box::new((vibration.clone(), at.clone()));
+            frank
+                .exec(FrankCommand::SetAlarm(side, alarm), TIMEOUT)
+                .await
+                .unwrap();
+        }
+        let right = sim.snapshot().alarm_right.unwrap();
+
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(frank.clone()))
+                .app_data(Data::new(settings_rx))
+                .configure(api::configure),
+        )
+        .await;
+
+        // Solo endpoints don't work in Couples mode
+        let req = TestRequest::post().uri("/both/alarm/dismiss").to_request();
+        assert!(call_service(&app, req).await.status().is_server_error());
+
+        // Frank clears both sides, so the right side is armed again
+        let req = TestRequest::post().uri("/left/alarm/dismiss").to_request();
+        assert!(call_service(&app, req).await.status().is_success());
+        eventually(|| async { sim.snapshot().alarm_right.as_ref() == Some(&right) }).await;
+        let snapshot = sim.snapshot();
+        assert!(snapshot.alarm_left.is_none());
+        assert_eq!(
+            snapshot.log.iter().filter(|(c, _)| *c == ALARM_CLEAR).count(),
+            1
+        );
+
+        let req = TestRequest::post()
+            .uri("/left/alarm/snooze")
+            .set_json(9)
+            .to_request();
+        assert!(call_service(&app, req).await.status().is_success());
+        let snapshot = sim.snapshot();
+        assert_eq!(snapshot.alarm_right, Some(right.clone()));
+        let cbor = hex::decode(snapshot.alarm_left.unwrap()).unwrap();
+        let left: TimestampedVibrationAlarm = ciborium::from_reader(&cbor[..]).unwrap();
+        let snoozed = Timestamp::now() + SignedDuration::from_mins(9);
+        assert!(snoozed.as_second().abs_diff(left.timestamp as i64) <= 5);
+
+        // the wake alarm is set again once a test alarm is over
+        let req = TestRequest::post()
+            .uri("/action/alarm")
+            .set_json(serde_json::json!({
+                "side": "right", "pattern": "double", "intensity": 30, "duration": 1, "delay": 0
+            }))
+            .to_request();
+        assert_eq!(call_and_read_body(&app, req).await, "ok");
+        assert_ne!(sim.snapshot().alarm_right, Some(right.clone()));
+        eventually(|| async { sim.snapshot().alarm_right.as_ref() == Some(&right) }).await;
+    }
+
+    #[actix_web::test]
+    async fn actions() {
+        let path = socket_path("actions");
+        let sim = FrankSim::new();
+        sim.connect(&path);
+
+        let (_, settings_rx) = watch::channel(away_settings("actions"));
+        let (frank, _, _) = frank::run(&path, settings_rx.clone()).await.unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(frank))
+                .app_data(Data::new(settings_rx))
+                .configure(api::configure),
+        )
+        .await;
+        let post = |uri: &str, body: serde_json::Value| {
+            TestRequest::post().uri(uri).set_json(body).to_request()
+        };
+
+        // Frank's own answer comes back when he refuses
+        sim.fail_next(PRIME, 1);
+        let req = TestRequest::post().uri("/action/prime").to_request();
+        let res = call_service(&app, req).await;
+        assert!(res.status().is_server_error());
+        assert_eq!(
+            actix_web::body::to_bytes(res.into_body()).await.unwrap(),
+            "error"
+        );
+
+        let req = TestRequest::post().uri("/action/prime").to_request();
+        assert_eq!(call_and_read_body(&app, req).await, "ok");
+        assert!(sim.snapshot().priming);
+
+        let req = post(
+            "/action/temp",
+            serde_json::json!({ "side": "right", "temp": { "c": 21 }, "duration": 600 }),
+        );
+        assert_eq!(call_and_read_body(&app, req).await, "ok");
+        assert_eq!(sim.snapshot().right.tar, -50);
+        assert!(sim.snapshot().right.time > 599.);
+
+        let req = post(
+            "/action/alarm",
+            serde_json::json!({ "side": "both", "pattern": "double", "intensity": 30, "duration": 10 }),
+        );
+        assert_eq!(call_and_read_body(&app, req).await, "ok");
+        let cbor = hex::decode(sim.snapshot().alarm_left.unwrap()).unwrap();
+        let alarm: TimestampedVibrationAlarm = ciborium::from_reader(&cbor[..]).unwrap();
+        assert_eq!(alarm.pattern, "double");
+        let soon = Timestamp::now() + SignedDuration::from_secs(5);
+        assert!(soon.as_second().abs_diff(alarm.timestamp as i64) <= 2);
+
+        let req = post("/action/clear_alarm", serde_json::json!("both"));
+        assert_eq!(call_and_read_body(&app, req).await, "ok");
+        assert!(sim.snapshot().alarm_left.is_none());
+        assert!(sim.snapshot().alarm_right.is_none());
+    }
+
+    #[actix_web::test]
+    async fn temp_override() {
+        let path = socket_path("override");
+        let sim = FrankSim::new();
+        sim.connect(&path);
+
+        let mut settings = away_settings("override");
+        let (settings_tx, settings_rx) = watch::channel(settings.clone());
+        let (frank, _, _) = frank::run(&path, settings_rx.clone()).await.unwrap();
+        actix_web::rt::spawn(scheduler::run(frank, settings_rx.clone()));
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(settings_rx))
+                .configure(api::configure),
+        )
+        .await;
+
+        // these fail before anything is saved
+        let bad = [
+            (
+                "/left/override",
+                serde_json::json!({ "temp": 10, "minutes": 90 }),
+            ),
+            ("/both/override", serde_json::json!({ "temp": 10 })),
+            (
+                "/both/override",
+                serde_json::json!({ "temp": { "c": 60 }, "until": "wake" }),
+            ),
+        ];
+        for (uri, body) in bad {
+            let req = TestRequest::post().uri(uri).set_json(body).to_request();
+            assert!(call_service(&app, req).await.status().is_server_error());
+        }
+
+        let temp = Temp::Celsius { c: 30. };
+        let until = Timestamp::now() + SignedDuration::from_mins(90);
+        settings.as_solo_mut().unwrap().temp_override = Some(TempOverride { temp, until });
+        settings_tx.send(settings).unwrap();
+
+        eventually(|| async {
+            let snapshot = sim.snapshot();
+            snapshot.left.tar == temp.level() && snapshot.right.tar == temp.level()
+        })
+        .await;
+        assert!(sim.snapshot().left.time > 5390.);
+
+        let req = TestRequest::get().uri("/both/override").to_request();
+        let body: serde_json::Value =
+            serde_json::from_slice(&call_and_read_body(&app, req).await).unwrap();
+        assert_eq!(body["until"], until.to_string());
+    }
+
+    #[actix_web::test]
+    async fn nap() {
+        let path = socket_path("nap");
+        let sim = FrankSim::new();
+        sim.connect(&path);
+
+        let mut settings = away_settings("nap");
+        let (settings_tx, settings_rx) = watch::channel(settings.clone());
+        let (frank, _, _) = frank::run(&path, settings_rx.clone()).await.unwrap();
+        actix_web::rt::spawn(scheduler::run(frank, settings_rx.clone()));
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(settings_rx))
+                .app_data(Data::new(settings_tx.clone()))
+                .configure(api::configure),
+        )
+        .await;
+
+        // these fail before anything is saved
+        let over = Timestamp::now() - SignedDuration::from_hours(2);
+        let bad = [
+            (
+                "/left/nap",
+                serde_json::json!({ "temp": 10, "minutes": 20 }),
+            ),
+            ("/both/nap", serde_json::json!({ "temp": 10, "minutes": 0 })),
+            (
+                "/both/nap",
+                serde_json::json!({ "temp": 10, "minutes": 20, "start": over }),
+            ),
+            (
+                "/both/nap",
+                serde_json::json!({ "temp": { "f": 120 }, "minutes": 20, "start": "14:00" }),
+            ),
+        ];
+        for (uri, body) in bad {
+            let req = TestRequest::post().uri(uri).set_json(body).to_request();
+            assert!(call_service(&app, req).await.status().is_server_error());
+        }
+
+        let nap = Nap {
+            start: Timestamp::now(),
+            minutes: 30,
+            temp: Temp::Level(-40),
+            vibration: Some(VibrationAlarm {
+                pattern: VibrationPattern::Rise,
+                intensity: 30,
+                duration: 60,
+                offset: 0,
+            }),
+        };
+        settings.as_solo_mut().unwrap().nap = Some(nap.clone());
+        settings_tx.send(settings).unwrap();
+
+        eventually(|| async {
+            let snapshot = sim.snapshot();
+            snapshot.left.tar == -40 && snapshot.alarm_left.is_some()
+        })
+        .await;
+        assert!(sim.snapshot().right.time > 1790.);
+
+        let req = TestRequest::get().uri("/both/nap").to_request();
+        let body: serde_json::Value =
+            serde_json::from_slice(&call_and_read_body(&app, req).await).unwrap();
+        assert_eq!(body["start"], nap.start.to_string());
+        assert_eq!(body["vibration"]["pattern"], "rise");
+    }
+
+    #[actix_web::test]
+    async fn exceptions() {
+        let mut settings = away_settings("exceptions");
+        let today = Timestamp::now().to_zoned(settings.timezone.clone()).date();
+        let exception = |id, from, skip| ScheduleException {
+            id,
+            from,
+            to: None,
+            skip,
+            sleep: None,
+            wake: (!skip).then(|| "05:30".parse().unwrap()),
+        };
+        let yesterday = today.yesterday().unwrap();
+        settings.as_solo_mut().unwrap().exceptions =
+            vec![exception(1, yesterday, true), exception(2, today, false)];
+
+        let (settings_tx, settings_rx) = watch::channel(settings);
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(settings_rx))
+                .app_data(Data::new(settings_tx))
+                .configure(api::configure),
+        )
+        .await;
+
+        // yesterday's is already over
+        let req = TestRequest::get().uri("/both/exceptions").to_request();
+        let body: Vec<ScheduleException> =
+            serde_json::from_slice(&call_and_read_body(&app, req).await).unwrap();
+        assert_eq!(body, vec![exception(2, today, false)]);
+
+        // these fail before anything is saved
+        let bad = [
+            serde_json::json!({ "from": yesterday.to_string(), "skip": true }),
+            serde_json::json!({ "from": today.to_string() }),
+            serde_json::json!({ "from": today.to_string(), "skip": true, "sleep": "23:00" }),
+        ];
+        for body in bad {
+            let req = TestRequest::post()
+                .uri("/both/exceptions")
+                .set_json(body)
+                .to_request();
+            let res = call_and_read_body(&app, req).await;
+            assert!(String::from_utf8_lossy(&res).starts_with("invalid exception"));
+        }
+
+        let req = TestRequest::delete().uri("/both/exceptions/7").to_request();
+        let res = call_and_read_body(&app, req).await;
+        assert_eq!(res, "there is no exception `7` for this side");
+        let req = TestRequest::get().uri("/left/exceptions").to_request();
+        assert!(call_service(&app, req).await.status().is_server_error());
+    }
+
+    #[actix_web::test]
+    async fn calendar() {
+        let settings = away_settings("calendar");
+        let (settings_tx, settings_rx) = watch::channel(settings.clone());
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(settings_rx))
+                .app_data(Data::new(settings_tx.clone()))
+                .configure(api::configure),
+        )
+        .await;
+
+        // nothing is saved without a calendar set
+        let req = TestRequest::get().uri("/calendar").to_request();
+        assert_eq!(
+            call_and_read_body(&app, req).await,
+            "there is no calendar set"
+        );
+        let req = TestRequest::post()
+            .uri("/calendar")
+            .set_payload("BEGIN:VCALENDAR\r\nEND:VCALENDAR\r\n")
+            .to_request();
+        assert_eq!(
+            call_and_read_body(&app, req).await,
+            "there is no calendar set"
+        );
+
+        // previewed with the default keywords, "away" and "early"
+        let req = TestRequest::post()
+            .uri("/calendar/preview")
+            .set_payload(include_str!("../fixtures/calendar.ics"))
+            .to_request();
+        let body: serde_json::Value =
+            serde_json::from_slice(&call_and_read_body(&app, req).await).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!([
+                {
+                    "summary": "Early standup with the Berlin office, bring the slides",
+                    "id": 0,
+                    "from": "2026-11-03",
+                    "skip": false,
+                    "wake": "02:30:00"
+                },
+                {
+                    "summary": "Flight home",
+                    "id": 0,
+                    "from": "2026-11-20",
+                    "skip": false,
+                    "wake": "04:15:00"
+                },
+                {
+                    "summary": "Cabin, no signal",
+                    "id": 0,
+                    "from": "2026-12-06",
+                    "skip": true
+                }
+            ])
+        );
+
+        let file = std::env::temp_dir().join(format!("opensleep-{}.ics", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+        settings_tx.send_modify(|s| {
+            s.calendar = Some(CalendarSettings {
+                file: file.to_string_lossy().to_string(),
+                ..Default::default()
+            })
+        });
+
+        // anything that isn't a calendar is turned away before it's written
+        let req = TestRequest::post()
+            .uri("/calendar")
+            .set_payload("<html>not found</html>")
+            .to_request();
+        assert!(
+            String::from_utf8_lossy(&call_and_read_body(&app, req).await)
+                .starts_with("not an iCalendar file")
+        );
+        assert!(!file.exists());
+
+        let req = TestRequest::post()
+            .uri("/calendar")
+            .set_payload(include_str!("../fixtures/calendar.ics"))
+            .to_request();
+        assert_eq!(call_and_read_body(&app, req).await, "OK");
+        let req = TestRequest::get().uri("/calendar").to_request();
+        let body: serde_json::Value =
+            serde_json::from_slice(&call_and_read_body(&app, req).await).unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 3);
+        let _ = std::fs::remove_file(&file);
     }
 }